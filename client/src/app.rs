@@ -1,19 +1,171 @@
-use std::{net::TcpStream, process, io::{self, Write}};
+use std::{net::TcpStream, process, time::{Duration, Instant}, path::PathBuf, sync::Arc};
 
 use retry::{delay::Fixed, retry_with_index};
 
-use eframe::{egui::{self, Key}};
+use eframe::egui;
 
-use file_share::{ShareCommand, Share, Location, Config, ShareCommandBuilder, CommandType};
+use file_share::{
+    Config, ShareCommand, ShareCommandBuilder, CommandType, Handshake, Progress, RESUME, Connection, TlsConnector, login,
+    DIRECTORY_SUFFIX, CatalogEntry, CatalogQuery, CatalogSort, Permission, SearchQuery, parse_date, ConflictPolicy,
+};
+
+use crate::{worker::{Worker, Event, local_name}, file_browser::FileBrowser};
+
+/// How often the window is redrawn while a transfer is running, so its progress moves
+const TRANSFER_REPAINT_DELAY: Duration = Duration::from_millis(100);
+/// Amount of characters of a checksum shown in the catalog, the rest is shown when the mouse is over it
+const CHECKSUM_PREVIEW_LEN: usize = 12;
+/// Amount of catalog entries asked for at once, the next page is asked for when the user scrolls to the end
+const CATALOG_PAGE_SIZE: u64 = 200;
 
 pub struct App {
-    stream: TcpStream,
-    share: Option<Share>,
-    catalog_cache: String,
+    /// Runs commands on the server in the background
+    worker: Worker,
+    /// Contains what was agreed on with the server when connecting
+    handshake: Handshake,
+    /// True if the connection is encrypted with TLS
+    encrypted: bool,
+    /// User we logged in as, None if the server did not need a login
+    user: Option<String>,
+    /// Pages of the directory the catalog shows that were received so far
+    catalog_cache: Vec<CatalogEntry>,
+    /// Filter and sort order the catalog is asked for with, the offset and limit are set for each page
+    catalog_query: CatalogQuery,
+    /// Text in the filter box, it is put in the query whenever it changes
+    catalog_filter: String,
+    /// Offset of the next page of the catalog, None once the last page was received
+    catalog_cursor: Option<u64>,
+    /// Amount of entries the running refresh asks for again page by page, 0 if no refresh is running
+    catalog_refresh_end: u64,
+    /// Amount of entries in the directory that match the filter
+    catalog_total: u64,
+    /// Directory on the server the catalog shows, empty for the storage root
+    catalog_dir: String,
+    /// How often the catalog is refreshed on its own
+    catalog_refresh_delay: Duration,
+    /// When the catalog was last requested
+    last_catalog_refresh: Option<Instant>,
+    /// True while a CATALOG command is waiting on the server
+    catalog_pending: bool,
+    /// Amount of commands sent to the worker that have not finished yet
+    pending: usize,
+    /// Last message shown to the user
+    status: String,
+    /// Window used to pick files to upload
+    file_browser: FileBrowser,
+    /// UPLOAD and RECEIVE commands that have not finished yet
+    transfers: Vec<Transfer>,
+    /// DELETE or RMDIR of the path the user asked to delete, it is only sent once they confirm
+    pending_delete: Option<(CommandType, String)>,
+    /// RENAME, COPY or MKDIR the user is entering a name for
+    pending_name: Option<NamePrompt>,
+    /// UPLOADs the server refused because a file is already there, the user picks what to do with them one at a time
+    pending_conflicts: Vec<ShareCommand>,
+    /// What the user entered in the search window
+    search_form: SearchForm,
+    /// Directory and query of the last search, later pages are asked for with the same query
+    search: Option<(String, SearchQuery)>,
+    /// Pages of the files the last search found that were received so far
+    search_results: Vec<CatalogEntry>,
+    /// Offset of the next page of search results, None once the last page was received
+    search_cursor: Option<u64>,
+    /// Amount of files the last search found
+    search_total: u64,
+    /// Metadata of the path shown in the details pane, its name is the path on the server
+    details: Option<CatalogEntry>,
+}
+
+/// Contains a command that waits for the user to enter a name on the server, like where a RENAME or COPY should go
+struct NamePrompt {
+    command_type: CommandType,
+    /// Path of the file the command is for, None for commands that only take the name
+    source: Option<String>,
+    /// Edited by the user, starts out as the source
+    name: String,
+}
+
+/// Contains the text fields of the search window, they are turned into a SearchQuery when the user searches
+#[derive(Default)]
+struct SearchForm {
+    /// True while the search window is shown
+    open: bool,
+    name: String,
+    /// Extensions separated by commas or spaces
+    extensions: String,
+    min_size: String,
+    max_size: String,
+    after: String,
+    before: String,
+}
+
+impl SearchForm {
+    /// Returns the query for what was entered, empty fields are left out
+    fn query(&self) -> Result<SearchQuery, String> {
+        let number = |field: &str, text: &str| -> Result<Option<u64>, String> {
+            match text.trim() {
+                "" => Ok(None),
+                text => text.parse().map(Some).map_err(|_| format!("{field} must be a number of bytes, got {text}")),
+            }
+        };
+        let date = |text: &str| -> Result<Option<u64>, String> {
+            match text.trim() {
+                "" => Ok(None),
+                text => parse_date(text).map(Some).map_err(|error| error.to_string()),
+            }
+        };
+
+        Ok(SearchQuery {
+            name: Some(self.name.trim().to_string()).filter(|name| !name.is_empty()),
+            extensions: self.extensions
+                .split([',', ' '])
+                .filter(|extension| !extension.is_empty())
+                .map(String::from)
+                .collect(),
+            min_size: number("Min size", &self.min_size)?,
+            max_size: number("Max size", &self.max_size)?,
+            modified_after: date(&self.after)?,
+            modified_before: date(&self.before)?,
+            ..SearchQuery::default()
+        })
+    }
 }
 
-impl Default for App {
-    fn default() -> Self {
+/// Contains an UPLOAD or RECEIVE shown in the transfers panel
+struct Transfer {
+    /// Shown to the user, like `Upload file.txt`
+    name: String,
+    progress: Arc<Progress>,
+    /// When the file body started moving, None while the command is still waiting in the queue
+    started: Option<Instant>,
+    /// Bytes that were already done when the body started moving, more than zero when the transfer was resumed
+    resumed_at: u64,
+}
+
+impl Transfer {
+    /// Returns the average speed (in bytes per second) since the file body started moving
+    fn speed(&self) -> Option<f64> {
+        let elapsed = self.started?.elapsed().as_secs_f64();
+
+        if elapsed > 0.0 {
+            Some(self.progress.done().saturating_sub(self.resumed_at) as f64 / elapsed)
+        } else {
+            None
+        }
+    }
+    /// Returns the estimated time left, based on the average speed
+    fn eta(&self) -> Option<Duration> {
+        let left = self.progress.total()?.saturating_sub(self.progress.done());
+
+        match self.speed() {
+            Some(speed) if speed > 0.0 => Some(Duration::from_secs_f64(left as f64 / speed)),
+            _ => None,
+        }
+    }
+}
+
+impl App {
+    /// Connect to the server and start the worker that owns the connection
+    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
         let config = Config::build("Config.toml").unwrap_or_else(|error| {
             eprintln!("Config build error: {error}");
             process::exit(1);
@@ -21,8 +173,21 @@ impl Default for App {
             eprintln!("Config build error: {error}");
             process::exit(1);
         });
-    
-        let stream = 
+
+        // Set up TLS before connecting so a bad config is reported right away
+        let tls = if config.uses_tls() {
+            let connector = TlsConnector::build(config.tls_ca(), config.tls_fingerprint(), config.tls_server_name())
+                .unwrap_or_else(|error| {
+                    eprintln!("Failed to set up TLS: {error}");
+                    process::exit(1);
+                });
+
+            Some(connector)
+        } else {
+            None
+        };
+
+        let stream =
         // Retry connecting to the server 10 times, once every 1000 milliseconds
         retry_with_index(Fixed::from_millis(config.retry_delay()).take(config.retry_amount()), |current_try| {
             match TcpStream::connect(config.server()) {
@@ -33,83 +198,963 @@ impl Default for App {
                 },
             }
         });
-    
+
         let stream = stream.unwrap_or_else(|error| {
             eprintln!("Failed to connect to server!: {error}");
             process::exit(1)
         });
 
-        println!("Connected to server!");
-        
-        Self { stream, share: None, catalog_cache: String::new() }
+        let mut stream = match &tls {
+            // Fails if the server is not the one we trust
+            Some(tls) => tls.connect(stream).unwrap_or_else(|error| {
+                eprintln!("Failed to set up an encrypted connection: {error}");
+                process::exit(1)
+            }),
+            None => Connection::Plain(stream),
+        };
+        let encrypted = stream.is_encrypted();
+
+        // The server tells us why if it can not talk to this version of the client
+        let handshake = Handshake::client(&mut stream, config.disabled_capabilities()).unwrap_or_else(|error| {
+            eprintln!("{error}");
+            process::exit(1)
+        });
+
+        println!("Connected to server! (protocol version {})", handshake.version());
+
+        // Servers with users refuse every command until we log in
+        let credentials = config.credentials();
+        let user = credentials.as_ref().map(|credentials| credentials.user.clone());
+        if let Some(credentials) = credentials {
+            login(&mut stream, credentials).unwrap_or_else(|error| {
+                eprintln!("Failed to log in: {error}");
+                process::exit(1)
+            });
+            println!("Logged in as {}", user.as_deref().unwrap_or_default());
+        }
+
+        // From here on the connection is only used by the worker thread
+        let keepalive = Duration::from_millis(config.keepalive_interval());
+        let worker = Worker::spawn(stream, cc.egui_ctx.clone(), handshake.supports(RESUME), keepalive);
+
+        Self {
+            worker,
+            handshake,
+            encrypted,
+            user,
+            catalog_cache: Vec::new(),
+            // Directories first, like the file browser
+            catalog_query: CatalogQuery { sort: CatalogSort::Kind, ..CatalogQuery::default() },
+            catalog_filter: String::new(),
+            catalog_cursor: None,
+            catalog_refresh_end: 0,
+            catalog_total: 0,
+            catalog_dir: String::new(),
+            catalog_refresh_delay: Duration::from_millis(config.catalog_refresh_delay()),
+            last_catalog_refresh: None,
+            catalog_pending: false,
+            pending: 0,
+            status: String::new(),
+            file_browser: FileBrowser::new(),
+            transfers: Vec::new(),
+            pending_delete: None,
+            pending_name: None,
+            pending_conflicts: Vec::new(),
+            search_form: SearchForm::default(),
+            search: None,
+            search_results: Vec::new(),
+            search_cursor: None,
+            search_total: 0,
+            details: None,
+        }
+    }
+    /// Ask the server for the catalog again. Every page that was already received is asked for again one after another, the
+    /// entries of a page are kept until it arrives so the list does not shrink while the user is scrolling through it
+    fn refresh_catalog(&mut self) {
+        self.last_catalog_refresh = Some(Instant::now());
+
+        if !self.catalog_pending {
+            self.catalog_refresh_end = self.catalog_cache.len() as u64;
+        }
+        self.request_catalog(0, CATALOG_PAGE_SIZE);
+    }
+    /// Ask the server for the next page of the catalog, if there is one
+    fn load_more_catalog(&mut self) {
+        if let Some(cursor) = self.catalog_cursor {
+            self.request_catalog(cursor, CATALOG_PAGE_SIZE);
+        }
+    }
+    /// Ask the server for a page of the catalog, unless a request is already on its way
+    fn request_catalog(&mut self, offset: u64, limit: u64) {
+        if self.catalog_pending {
+            return;
+        }
+
+        self.catalog_pending = true;
+
+        let query = CatalogQuery { offset, limit: Some(limit), ..self.catalog_query.clone() };
+        let mut command = ShareCommandBuilder::new().command_type(CommandType::Catalog).query(query);
+        if !self.catalog_dir.is_empty() {
+            command = command.arg(self.catalog_dir.clone());
+        }
+        self.send(command.build());
+    }
+    /// Throw away the pages that were received and start over from the first one, used when the directory, filter or sort
+    /// order changed
+    fn reload_catalog(&mut self) {
+        self.catalog_cache.clear();
+        self.catalog_cursor = None;
+        self.catalog_total = 0;
+        self.refresh_catalog();
+    }
+    /// Show the given directory on the server in the catalog
+    fn open_dir(&mut self, dir: String) {
+        self.catalog_dir = dir;
+        self.reload_catalog();
+    }
+    /// Sort the catalog the given way, picking the way it is already sorted flips the order
+    fn sort_catalog_by(&mut self, sort: CatalogSort) {
+        if sort == self.catalog_query.sort {
+            self.catalog_query.descending = !self.catalog_query.descending;
+        } else {
+            self.catalog_query.sort = sort;
+            self.catalog_query.descending = false;
+        }
+
+        self.reload_catalog();
+    }
+    /// Returns true if a CATALOG command was for the directory, filter and sort order shown now
+    fn is_current_catalog(&self, command: &ShareCommand) -> bool {
+        let query = command.query().cloned().unwrap_or_default();
+
+        command.arg().unwrap_or_default() == self.catalog_dir &&
+            query.filter == self.catalog_query.filter &&
+            query.sort == self.catalog_query.sort &&
+            query.descending == self.catalog_query.descending
+    }
+    /// Returns the path on the server of an entry in the directory the catalog shows
+    fn remote_path(&self, name: &str) -> String {
+        if self.catalog_dir.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}{DIRECTORY_SUFFIX}{name}", self.catalog_dir)
+        }
+    }
+    /// Upload a file or directory from the given path, it is stored in the directory the catalog shows under its own name
+    fn upload(&mut self, path: PathBuf) {
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => {
+                self.status = format!("Can not upload {}: it has no name", path.display());
+                return;
+            }
+        };
+
+        self.status = format!("Uploading {name}");
+        self.send(
+            ShareCommandBuilder::new()
+                .command_type(CommandType::Upload)
+                .arg(self.remote_path(&name))
+                .local_path(path)
+                // The user is asked what to do instead of following the default of the server
+                .conflict(ConflictPolicy::Fail)
+                .build()
+        );
+    }
+    /// Receive a file or directory from the directory the catalog shows, it is stored in the current directory under its own name
+    fn receive(&mut self, name: &str) {
+        self.receive_as(self.remote_path(name), name);
+    }
+    /// Receive the file or directory at the given path on the server, it is stored in the current directory under the given name.
+    /// The name comes from the server, it is refused if it would be stored anywhere else
+    fn receive_as(&mut self, path: String, name: &str) {
+        let local_path = match local_name(name) {
+            Ok(local_path) => local_path.to_path_buf(),
+            Err(error) => {
+                self.status = format!("Can not receive {path}: {error}");
+                return;
+            }
+        };
+
+        self.status = format!("Receiving {path}");
+        self.send(
+            ShareCommandBuilder::new()
+                .command_type(CommandType::Receive)
+                .arg(path)
+                .local_path(local_path)
+                .build()
+        );
+    }
+    /// Search the directory the catalog shows for what the user entered in the search window, the results replace the
+    /// earlier ones
+    fn start_search(&mut self) {
+        let query = match self.search_form.query() {
+            Ok(query) => query,
+            Err(error) => {
+                self.status = error;
+                return;
+            }
+        };
+
+        let query = SearchQuery { limit: Some(CATALOG_PAGE_SIZE), ..query };
+        self.search = Some((self.catalog_dir.clone(), query));
+        self.search_results.clear();
+        self.search_cursor = None;
+        self.search_total = 0;
+        self.request_search(0);
+    }
+    /// Ask the server for the page of the last search at the given offset
+    fn request_search(&mut self, offset: u64) {
+        let (dir, query) = match &self.search {
+            Some(search) => search.clone(),
+            None => return,
+        };
+
+        self.status = format!("Searching {DIRECTORY_SUFFIX}{dir}");
+
+        let query = SearchQuery { offset, ..query };
+        let mut command = ShareCommandBuilder::new().command_type(CommandType::Search).search(query);
+        if !dir.is_empty() {
+            command = command.arg(dir);
+        }
+        self.send(command.build());
+    }
+    /// Show the search window with the files the last search found, they can be downloaded from there
+    fn show_search(&mut self, ctx: &egui::Context) {
+        let mut open = self.search_form.open;
+        let mut search = false;
+        let mut load_more = false;
+        let mut clicked = None;
+
+        egui::Window::new("Search")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.label(format!("Files in {DIRECTORY_SUFFIX}{} and its subdirectories", self.catalog_dir));
+
+                let form = &mut self.search_form;
+                egui::Grid::new("search_form").show(ui, |ui| {
+                    ui.label("Name");
+                    ui.text_edit_singleline(&mut form.name).on_hover_text("Part of a name, or a pattern like report*");
+                    ui.end_row();
+                    ui.label("Extensions");
+                    ui.text_edit_singleline(&mut form.extensions).on_hover_text("Like txt, pdf");
+                    ui.end_row();
+                    ui.label("Size (bytes)");
+                    ui.horizontal(|ui| {
+                        ui.add(egui::TextEdit::singleline(&mut form.min_size).hint_text("min").desired_width(100.0));
+                        ui.add(egui::TextEdit::singleline(&mut form.max_size).hint_text("max").desired_width(100.0));
+                    });
+                    ui.end_row();
+                    ui.label("Modified");
+                    ui.horizontal(|ui| {
+                        ui.add(egui::TextEdit::singleline(&mut form.after).hint_text("after YYYY-MM-DD").desired_width(100.0));
+                        ui.add(egui::TextEdit::singleline(&mut form.before).hint_text("before YYYY-MM-DD").desired_width(100.0));
+                    });
+                    ui.end_row();
+                });
+
+                ui.horizontal(|ui| {
+                    search = ui.button("Search").clicked();
+                    if self.search.is_some() {
+                        ui.label(format!("{} of {} files", self.search_results.len(), self.search_total));
+                    }
+                });
+
+                ui.separator();
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    egui::Grid::new("search_results").striped(true).show(ui, |ui| {
+                        ui.label("Path");
+                        ui.label("Size");
+                        ui.label("Modified");
+                        ui.end_row();
+
+                        for entry in &self.search_results {
+                            let button = egui::Button::new(&entry.name);
+                            if ui.add_enabled(entry.allows(Permission::Read), button).on_hover_text("Download").clicked() {
+                                clicked = Some(entry.name.clone());
+                            }
+                            ui.label(format_bytes(entry.size));
+                            ui.label(entry.modified.map_or_else(|| String::from("-"), format_time));
+                            ui.end_row();
+                        }
+                    });
+
+                    if self.search_cursor.is_some() {
+                        load_more = ui.button("Load more").clicked();
+                    }
+                });
+            });
+
+        self.search_form.open = open;
+        if search {
+            self.start_search();
+        }
+        if load_more {
+            if let Some(cursor) = self.search_cursor {
+                self.request_search(cursor);
+            }
+        }
+        // Found files are stored in the current directory under their own name, not the path they were found under
+        if let (Some(name), Some((dir, _))) = (clicked, &self.search) {
+            let path = if dir.is_empty() { name } else { format!("{dir}{DIRECTORY_SUFFIX}{name}") };
+            let file_name = path.rsplit(DIRECTORY_SUFFIX).next().unwrap_or_default().to_string();
+
+            self.receive_as(path, &file_name);
+        }
+    }
+    /// Ask the server for the metadata of the given path, it is shown in the details pane once it arrives
+    fn stat(&mut self, path: String) {
+        self.send(
+            ShareCommandBuilder::new()
+                .command_type(CommandType::Stat)
+                .arg(path)
+                .build()
+        );
+    }
+    /// Show the metadata of a single path next to the catalog
+    fn show_details(&mut self, ctx: &egui::Context) {
+        let entry = match &self.details {
+            Some(entry) => entry.clone(),
+            None => return,
+        };
+
+        let mut closed = false;
+        let mut refreshed = false;
+        let mut received = false;
+        egui::SidePanel::left("details")
+            .min_width(250.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.heading("Details");
+                    closed = ui.button("Close").clicked();
+                    refreshed = ui.button("Refresh").clicked();
+                });
+                ui.separator();
+
+                egui::Grid::new("details").show(ui, |ui| {
+                    ui.label("Path");
+                    ui.label(&entry.name);
+                    ui.end_row();
+                    ui.label("Kind");
+                    ui.label(format!("{:?}", entry.kind));
+                    ui.end_row();
+                    if !entry.is_dir() {
+                        ui.label("Size");
+                        ui.label(format!("{} ({} bytes)", format_bytes(entry.size), entry.size));
+                        ui.end_row();
+                    }
+                    ui.label("Modified");
+                    ui.label(entry.modified.map_or_else(|| String::from("-"), format_time));
+                    ui.end_row();
+                    ui.label("Permissions");
+                    ui.label(entry.permissions.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "));
+                    ui.end_row();
+                });
+
+                if let Some(checksum) = &entry.checksum {
+                    ui.horizontal(|ui| {
+                        ui.label("SHA-256");
+                        // So it can be compared with the checksum of a local file
+                        if ui.small_button("Copy").clicked() {
+                            ui.output().copied_text = checksum.to_string();
+                        }
+                    });
+                    ui.monospace(checksum.to_string());
+                }
+
+                let button = egui::Button::new("Download");
+                received = ui.add_enabled(entry.allows(Permission::Read), button).clicked();
+            });
+
+        if closed {
+            self.details = None;
+        } else if refreshed {
+            self.stat(entry.name);
+        } else if received {
+            let file_name = entry.name.rsplit(DIRECTORY_SUFFIX).next().unwrap_or_default().to_string();
+
+            self.receive_as(entry.name, &file_name);
+        }
+    }
+    /// Returns true if a SEARCH command was for the last search
+    fn is_current_search(&self, command: &ShareCommand) -> bool {
+        let query = SearchQuery { offset: 0, ..command.search().cloned().unwrap_or_default() };
+
+        self.search.as_ref().is_some_and(|search| *search == (command.arg().unwrap_or_default().to_string(), query))
+    }
+    /// Ask the user to confirm the file or directory they want to delete, the DELETE or RMDIR command is only sent once they do
+    fn show_delete_confirmation(&mut self, ctx: &egui::Context) {
+        let (command_type, path) = match &self.pending_delete {
+            Some(pending) => pending.clone(),
+            None => return,
+        };
+
+        let mut confirmed = false;
+        let mut cancelled = false;
+        egui::Window::new("Delete")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                if command_type == CommandType::Rmdir {
+                    ui.label(format!("Remove the directory {path} from the server? Only empty directories can be removed"));
+                } else {
+                    ui.label(format!("Delete {path} from the server? This can not be undone"));
+                }
+
+                ui.horizontal(|ui| {
+                    confirmed = ui.button("Delete").clicked();
+                    cancelled = ui.button("Cancel").clicked();
+                });
+            });
+
+        if confirmed {
+            self.pending_delete = None;
+            self.status = format!("Deleting {path}");
+            self.send(
+                ShareCommandBuilder::new()
+                    .command_type(command_type)
+                    .arg(path)
+                    .build()
+            );
+        } else if cancelled {
+            self.pending_delete = None;
+        }
+    }
+    /// Ask the user what to do with an upload that went to a file that is already on the server, it is sent again with the
+    /// policy they pick
+    fn show_conflict_prompt(&mut self, ctx: &egui::Context) {
+        let command = match self.pending_conflicts.first() {
+            Some(command) => command.clone(),
+            None => return,
+        };
+
+        let mut picked = None;
+        let mut cancelled = false;
+        egui::Window::new("File already exists")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(format!("{} is already on the server", command.arg().unwrap_or_default()));
+                if self.pending_conflicts.len() > 1 {
+                    ui.label(format!("{} more uploads are waiting for an answer", self.pending_conflicts.len() - 1));
+                }
+
+                ui.horizontal(|ui| {
+                    let policies = [
+                        (ConflictPolicy::Overwrite, "Overwrite"),
+                        (ConflictPolicy::KeepBoth, "Keep both"),
+                        (ConflictPolicy::OverwriteIfNewer, "Overwrite if newer"),
+                    ];
+                    for (policy, label) in policies {
+                        if ui.button(label).clicked() {
+                            picked = Some(policy);
+                        }
+                    }
+                    cancelled = ui.button("Cancel").clicked();
+                });
+            });
+
+        if let Some(policy) = picked {
+            self.pending_conflicts.remove(0);
+
+            let mut upload = ShareCommandBuilder::new()
+                .command_type(CommandType::Upload)
+                .arg(command.arg().unwrap_or_default().to_string())
+                .conflict(policy);
+            if let Some(local_path) = command.local_path() {
+                upload = upload.local_path(local_path.to_path_buf());
+            }
+
+            self.status = format!("Uploading {}", command.arg().unwrap_or_default());
+            self.send(upload.build());
+        } else if cancelled {
+            self.pending_conflicts.remove(0);
+        }
+    }
+    /// Ask the user for the name a RENAME, COPY or MKDIR should use, the command is only sent once they confirm
+    fn show_name_prompt(&mut self, ctx: &egui::Context) {
+        let prompt = match &mut self.pending_name {
+            Some(prompt) => prompt,
+            None => return,
+        };
+
+        let mut confirmed = false;
+        let mut cancelled = false;
+        egui::Window::new(format!("{:?}", prompt.command_type))
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                match &prompt.source {
+                    Some(source) => ui.label(format!("New name for {source}")),
+                    None => ui.label("Name of the new directory"),
+                };
+                let response = ui.text_edit_singleline(&mut prompt.name);
+                let entered = response.lost_focus() && ui.input().key_pressed(egui::Key::Enter);
+
+                ui.horizontal(|ui| {
+                    confirmed = ui.button("Ok").clicked() || entered;
+                    cancelled = ui.button("Cancel").clicked();
+                });
+            });
+
+        if confirmed && prompt.source.as_ref() != Some(&prompt.name) && !prompt.name.is_empty() {
+            let prompt = self.pending_name.take().unwrap();
+
+            let mut command = ShareCommandBuilder::new().command_type(prompt.command_type);
+            if let Some(source) = prompt.source {
+                command = command.arg(source);
+            }
+            let command = command.arg(prompt.name).build();
+
+            self.status = format!("{:?} {}", prompt.command_type, command.args().join(" "));
+            self.send(command);
+        } else if cancelled {
+            self.pending_name = None;
+        }
+    }
+    /// Queue a command on the worker, UPLOAD and RECEIVE commands are shown in the transfers panel until they finish
+    fn send(&mut self, command: ShareCommand) {
+        let progress = Progress::new();
+
+        if let Some(command_type @ (CommandType::Upload | CommandType::Receive)) = command.command_type() {
+            self.transfers.push(Transfer {
+                name: format!("{command_type:?} {}", command.arg().unwrap_or_default()),
+                progress: Arc::clone(&progress),
+                started: None,
+                resumed_at: 0,
+            });
+        }
+
+        self.pending += 1;
+        self.worker.send(command, progress);
+    }
+    /// Handle everything the worker finished since the last frame
+    fn handle_events(&mut self) {
+        for event in self.worker.poll() {
+            match event {
+                Event::Finished { command, progress, result, conflicts } => {
+                    self.pending -= 1;
+
+                    // Only uploads the GUI sent itself are asked about, the user already picked a policy for the others
+                    let unanswered = conflicts.into_iter().filter(|conflict| conflict.conflict() == Some(ConflictPolicy::Fail));
+                    self.pending_conflicts.extend(unanswered);
+                    self.transfers.retain(|transfer| !Arc::ptr_eq(&transfer.progress, &progress));
+
+                    let is_catalog = command.command_type() == Some(&CommandType::Catalog);
+                    if is_catalog {
+                        self.catalog_pending = false;
+                    }
+                    let is_search = command.command_type() == Some(&CommandType::Search);
+
+                    match result {
+                        // The user opened another directory or changed the filter while this was on its way
+                        Ok(_) if is_catalog && !self.is_current_catalog(&command) => {
+                            self.reload_catalog();
+                        }
+                        Ok(share) if is_catalog => {
+                            let page = share.catalog().cloned().unwrap_or_default();
+                            let offset = command.query().map_or(0, |query| query.offset);
+                            if offset > self.catalog_cache.len() as u64 {
+                                continue;
+                            }
+
+                            // A page replaces the entries it covers, while refreshing the entries after it are kept until
+                            // their own page arrives
+                            let received = offset + page.entries.len() as u64;
+                            let refreshing = received < self.catalog_refresh_end && page.cursor.is_some();
+                            let kept = if refreshing {
+                                self.catalog_cache.split_off((received as usize).min(self.catalog_cache.len()))
+                            } else {
+                                Vec::new()
+                            };
+                            self.catalog_cache.truncate(offset as usize);
+                            self.catalog_cache.extend(page.entries);
+                            self.catalog_cache.extend(kept);
+                            self.catalog_total = page.total;
+
+                            if refreshing {
+                                self.request_catalog(received, CATALOG_PAGE_SIZE);
+                            } else {
+                                self.catalog_refresh_end = 0;
+                                self.catalog_cursor = page.cursor;
+                            }
+                        }
+                        Ok(share) if command.command_type() == Some(&CommandType::Stat) => {
+                            self.details = share.stat().cloned();
+                        }
+                        Ok(share) if is_search => {
+                            let page = share.catalog().cloned().unwrap_or_default();
+                            let offset = command.search().map_or(0, |query| query.offset);
+
+                            // Pages of an earlier search, or one that arrives twice, are dropped
+                            if !self.is_current_search(&command) || offset != self.search_results.len() as u64 {
+                                continue;
+                            }
+                            self.search_results.extend(page.entries);
+                            self.search_cursor = page.cursor;
+                            self.search_total = page.total;
+                            self.status = format!("Found {} files", page.total);
+                        }
+                        Ok(share) => {
+                            self.status = format!(
+                                "{:?} {} finished",
+                                command.command_type().unwrap(),
+                                command.args().join(" "),
+                            );
+                            // Both sides agreed on this checksum, show it so the file can be checked by hand too
+                            if let Some(checksum) = share.checksum() {
+                                self.status.push_str(&format!(" (SHA-256 {checksum})"));
+                            }
+
+                            // Show new, deleted and moved files right away instead of waiting for the timer
+                            let changes_catalog = matches!(
+                                command.command_type(),
+                                Some(
+                                    CommandType::Upload | CommandType::Delete | CommandType::Rename | CommandType::Copy |
+                                    CommandType::Mkdir | CommandType::Rmdir
+                                ),
+                            );
+                            if changes_catalog {
+                                self.refresh_catalog();
+                            }
+                        }
+                        // Only uploads the GUI sent itself are asked about, the user already picked a policy for the others
+                        Err(error) if error.is_conflict() && command.conflict() == Some(ConflictPolicy::Fail) => {
+                            self.status = error.to_string();
+                            self.pending_conflicts.push(command);
+                        }
+                        Err(error) if error.is_cancelled() => {
+                            self.status = format!(
+                                "{:?} {} cancelled",
+                                command.command_type().unwrap(),
+                                command.args().join(" "),
+                            );
+                        }
+                        Err(error) => {
+                            eprintln!("Error occurred: {error}");
+                            self.status = error.to_string();
+                        }
+                    }
+                }
+            }
+        }
     }
 }
 
 impl eframe::App for App {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        let mut command = ShareCommandBuilder::new()
-            .command_type(CommandType::Catalog)
-            .build();
+        self.handle_events();
 
-        egui::CentralPanel::default()
+        // Refresh the catalog on a timer, the repaint request makes sure we wake up in time even if the user does nothing
+        let refresh_due = self.last_catalog_refresh
+            .is_none_or(|last_refresh| last_refresh.elapsed() >= self.catalog_refresh_delay);
+        if refresh_due {
+            self.refresh_catalog();
+        }
+        ctx.request_repaint_after(self.catalog_refresh_delay);
+
+        // Upload files dropped onto the window
+        let dropped_files = ctx.input().raw.dropped_files.clone();
+        for file in dropped_files {
+            match file.path {
+                Some(path) if path.exists() => self.upload(path),
+                Some(path) => self.status = format!("Can not upload {}: it does not exist", path.display()),
+                None => self.status = format!("Can not upload {}: no path was given", file.name),
+            }
+        }
+
+        if let Some(path) = self.file_browser.show(ctx) {
+            self.upload(path);
+        }
+
+        self.show_delete_confirmation(ctx);
+        self.show_name_prompt(ctx);
+        self.show_conflict_prompt(ctx);
+        self.show_search(ctx);
+        self.show_details(ctx);
+
+        // Keep the progress bars moving
+        if !self.transfers.is_empty() {
+            ctx.request_repaint_after(TRANSFER_REPAINT_DELAY);
+        }
+
+        egui::SidePanel::right("transfers")
+            .min_width(300.0)
             .show(ctx, |ui| {
-                // Update server catalog
-                ui.label("Catalog");
-            
-                // Read in the response the server send, this can contain requested files, text data, etc.
-                self.share = Some(send_to_server_receive_from_server(&mut self.stream, &command));
-   
-                if command.command_type_is(CommandType::Catalog) {
-                    self.catalog_cache = self.share.as_ref().unwrap().text_data.as_ref().unwrap().to_string();
+                ui.heading("Transfers");
+
+                if self.transfers.is_empty() {
+                    ui.label("Nothing is being transferred");
                 }
 
-                // Display catalog with each file as a button
-                for file in self.catalog_cache.split_whitespace() {
-                    if ui.button(file).clicked() {
-                        command = ShareCommandBuilder::new()
-                            .command_type(CommandType::Receive)
-                            .arg(file.to_string())
-                            .build();
+                for transfer in &mut self.transfers {
+                    ui.separator();
+                    show_transfer(ui, transfer);
+                }
+            });
+
+        egui::TopBottomPanel::bottom("status")
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if self.pending > 0 {
+                        ui.spinner();
+                    }
+                    ui.label(&self.status);
+                });
+            });
+
+        egui::CentralPanel::default()
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "Connected to server (protocol version {}, {}){}",
+                    self.handshake.version(),
+                    if self.encrypted { "encrypted" } else { "NOT encrypted" },
+                    self.user.as_ref().map(|user| format!(" as {user}")).unwrap_or_default(),
+                ));
+
+                let mut opened = None;
+                ui.horizontal(|ui| {
+                    ui.label(format!("Catalog {DIRECTORY_SUFFIX}{}", self.catalog_dir));
+
+                    if !self.catalog_dir.is_empty() && ui.button("Up").clicked() {
+                        let parent = self.catalog_dir.rsplit_once(DIRECTORY_SUFFIX).map_or("", |(parent, _)| parent);
+                        opened = Some(parent.to_string());
                     }
+                    if ui.button("Refresh").clicked() {
+                        self.refresh_catalog();
+                    }
+                    if ui.button("Upload").clicked() {
+                        self.file_browser.open();
+                    }
+                    if ui.button("Search").clicked() {
+                        self.search_form.open = true;
+                    }
+                    if ui.button("New folder").clicked() {
+                        self.pending_name = Some(NamePrompt {
+                            command_type: CommandType::Mkdir,
+                            source: None,
+                            name: self.remote_path(""),
+                        });
+                    }
+                });
+
+                let mut filter_changed = false;
+                ui.horizontal(|ui| {
+                    ui.label("Filter");
+                    filter_changed = ui.text_edit_singleline(&mut self.catalog_filter)
+                        .on_hover_text("Part of a name, or a pattern like *.txt")
+                        .changed();
+                    ui.label(format!("{} of {} entries", self.catalog_cache.len(), self.catalog_total));
+                });
+                if filter_changed {
+                    self.catalog_query.filter = Some(self.catalog_filter.clone()).filter(|filter| !filter.is_empty());
+                    self.reload_catalog();
+                }
+
+                // Let the user know files can be dropped while they are dragged over the window
+                if !ctx.input().raw.hovered_files.is_empty() {
+                    ui.separator();
+                    ui.heading("Drop files to upload them");
                 }
 
-                // Send the command to the server and get a response (Command will still be CATALOG if none of the buttons were clicked)
-                self.share = Some(send_to_server_receive_from_server(&mut self.stream, &command));
+                // Display the catalog as a table, clicking a column header sorts by it. Clicking a file downloads it in the
+                // background, clicking a directory opens it
+                let mut clicked = None;
+                let mut sort_by = None;
+                let mut pending_name = None;
+                let mut pending_delete = None;
+                let mut details = None;
+                let mut load_more = false;
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    egui::Grid::new("catalog").striped(true).show(ui, |ui| {
+                        for sort in CatalogSort::ALL {
+                            let arrow = match self.catalog_query.descending {
+                                _ if sort != self.catalog_query.sort => "",
+                                false => " ^",
+                                true => " v",
+                            };
+                            if ui.button(format!("{sort:?}{arrow}")).clicked() {
+                                sort_by = Some(sort);
+                            }
+                        }
+                        ui.label("Permissions");
+                        ui.label("SHA-256");
+                        ui.end_row();
+
+                        for entry in &self.catalog_cache {
+                            let path = self.remote_path(&entry.name);
+
+                            if entry.is_dir() {
+                                let button = egui::Button::new(format!("[dir] {}", entry.name));
+                                if ui.add_enabled(entry.allows(Permission::List), button).clicked() {
+                                    opened = Some(path.clone());
+                                }
+                            } else {
+                                let button = egui::Button::new(&entry.name);
+                                if ui.add_enabled(entry.allows(Permission::Read), button).clicked() {
+                                    clicked = Some(entry.name.clone());
+                                }
+                            }
+                            ui.label(format!("{:?}", entry.kind));
+                            ui.label(if entry.is_dir() { String::from("-") } else { format_bytes(entry.size) });
+                            ui.label(entry.modified.map_or_else(|| String::from("-"), format_time));
+                            ui.label(entry.permissions.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "));
+                            match &entry.checksum {
+                                // The whole checksum does not fit, it is shown when the mouse is over it
+                                Some(checksum) => {
+                                    let checksum = checksum.to_string();
+                                    ui.label(format!("{}...", &checksum[..CHECKSUM_PREVIEW_LEN])).on_hover_text(checksum);
+                                }
+                                None => { ui.label("-"); }
+                            }
+
+                            ui.horizontal(|ui| {
+                                let button = egui::Button::new("Details").small();
+                                if ui.add_enabled(entry.allows(Permission::List), button).clicked() {
+                                    details = Some(path.clone());
+                                }
+                                if entry.is_dir() {
+                                    let button = egui::Button::new("Download").small();
+                                    if ui.add_enabled(entry.allows(Permission::Read), button).clicked() {
+                                        clicked = Some(entry.name.clone());
+                                    }
+                                }
+
+                                // Directories can be moved but not copied, the server checks the destination
+                                let command_types: &[(CommandType, Permission)] = if entry.is_dir() {
+                                    &[(CommandType::Rename, Permission::Write)]
+                                } else {
+                                    &[(CommandType::Rename, Permission::Write), (CommandType::Copy, Permission::Read)]
+                                };
+                                for (command_type, permission) in command_types {
+                                    let button = egui::Button::new(format!("{command_type:?}")).small();
+                                    if ui.add_enabled(entry.allows(*permission), button).clicked() {
+                                        pending_name = Some(NamePrompt {
+                                            command_type: *command_type,
+                                            source: Some(path.clone()),
+                                            name: path.clone(),
+                                        });
+                                    }
+                                }
+
+                                let button = egui::Button::new("Delete").small();
+                                if ui.add_enabled(entry.allows(Permission::Write), button).clicked() {
+                                    let command_type = if entry.is_dir() { CommandType::Rmdir } else { CommandType::Delete };
+                                    pending_delete = Some((command_type, path.clone()));
+                                }
+                            });
+                            ui.end_row();
+                        }
+                    });
 
-                // Execute the command
-                self.share.as_mut().unwrap().execute().unwrap();
+                    // Ask for the next page once the end of the list scrolls into view
+                    if self.catalog_cursor.is_some() {
+                        let response = ui.label("Loading more...");
+                        load_more = ui.is_rect_visible(response.rect);
+                    }
+                });
+
+                if let Some(sort) = sort_by {
+                    self.sort_catalog_by(sort);
+                }
+                if load_more {
+                    self.load_more_catalog();
+                }
+                if pending_name.is_some() {
+                    self.pending_name = pending_name;
+                }
+                if pending_delete.is_some() {
+                    self.pending_delete = pending_delete;
+                }
+                if let Some(path) = details {
+                    self.stat(path);
+                }
+                if let Some(name) = clicked {
+                    self.receive(&name);
+                }
+                if let Some(dir) = opened {
+                    self.open_dir(dir);
+                }
             });
     }
 }
 
-fn send_to_server_receive_from_server(stream: &mut TcpStream, command: &ShareCommand) -> Share {
-    let mut share = Share::new(command.clone(), Location::Client);
-    // Prepare data (if needed) for the specified command
-    if let Err(error) = share.prepare_data() {
-        // The error message should be clear to the user (file not found, is a directory, etc.) but better error handling will be 
-        // added later
-        eprintln!("Error occured while preparing data: {error}");
-        process::exit(1);
-    }
-
-    // Write the share we prepared to the server/stream
-    share.write_to_stream(stream, Location::Client).unwrap_or_else(|error| {
-        // will handle these errors later.
-        eprintln!("Error occurred: {error}");
-        process::exit(1);
-    });
-
-    // Make sure all buffered contents reach there destination
-    stream.flush().unwrap_or_else(|error| {
-        // will handle these errors later.
-        eprintln!("Error occurred: {error}");
-        process::exit(1);
-    });
-
-    // Read in the response the server send, this can contain requested files, text data, etc.
-    Share::read_from_stream(stream, Location::Client).unwrap_or_else(|error| {
-        // will handle these errors later.
-        eprintln!("Error occurred: {error}");
-        process::exit(1);
-    })
-}
\ No newline at end of file
+/// Draw a single transfer with its progress bar, speed, ETA and cancel button
+fn show_transfer(ui: &mut egui::Ui, transfer: &mut Transfer) {
+    let progress = &transfer.progress;
+
+    ui.label(&transfer.name);
+
+    let total = match progress.total() {
+        Some(total) => total,
+        None => {
+            ui.label("Waiting...");
+            return;
+        }
+    };
+    // The body started moving, speed and ETA are measured from here
+    if transfer.started.is_none() {
+        transfer.started = Some(Instant::now());
+        transfer.resumed_at = progress.done();
+    }
+
+    let fraction = if total > 0 { progress.done() as f32 / total as f32 } else { 1.0 };
+    ui.add(egui::ProgressBar::new(fraction).show_percentage());
+
+    if transfer.resumed_at > 0 {
+        ui.label(format!("Resumed at {}", format_bytes(transfer.resumed_at)));
+    }
+
+    let speed = transfer.speed()
+        .map_or_else(|| String::from("-"), |speed| format!("{}/s", format_bytes(speed as u64)));
+    let eta = transfer.eta()
+        .map_or_else(|| String::from("-"), |eta| format!("{}s", eta.as_secs()));
+    ui.label(format!(
+        "{} / {}, {speed}, {eta} left",
+        format_bytes(progress.done()),
+        format_bytes(total),
+    ));
+
+    if progress.is_cancelled() {
+        ui.label("Cancelling...");
+    } else if ui.button("Cancel").clicked() {
+        progress.cancel();
+    }
+}
+
+/// Format a time in seconds since the Unix epoch as a UTC date and time, like `2024-01-31 13:05`
+fn format_time(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let time = secs % 86_400;
+
+    // Turn the days since 1970-01-01 into a date, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02} {:02}:{:02}", time / 3_600, time % 3_600 / 60)
+}
+
+/// Format a size in bytes with a readable unit
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} {}", UNITS[0])
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
@@ -0,0 +1,114 @@
+use std::{env, fs, path::{Path, PathBuf}};
+
+use eframe::egui;
+
+/// A simple file chooser drawn with egui, so no native dialog library is needed
+pub struct FileBrowser {
+    /// True while the window is shown
+    open: bool,
+    /// Directory currently being shown
+    dir: PathBuf,
+    /// Entries of dir, directories first. These are only read when the directory changes, not every frame
+    entries: Vec<Entry>,
+    /// Error from reading dir, shown instead of the entries
+    error: Option<String>,
+}
+
+/// Contains a single entry of the directory being shown
+struct Entry {
+    name: String,
+    path: PathBuf,
+    is_dir: bool,
+}
+
+impl FileBrowser {
+    /// Create a new FileBrowser that starts in the current directory
+    pub fn new() -> FileBrowser {
+        let dir = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+
+        FileBrowser { open: false, dir, entries: Vec::new(), error: None }
+    }
+    /// Show the window, it stays open until a file or directory is picked or it is closed
+    pub fn open(&mut self) {
+        self.open = true;
+        self.change_dir(self.dir.clone());
+    }
+    /// Draw the window if it is open, returns the file or directory that was picked (if any)
+    pub fn show(&mut self, ctx: &egui::Context) -> Option<PathBuf> {
+        let mut open = self.open;
+        let mut picked = None;
+        let mut new_dir = None;
+
+        egui::Window::new("Choose a file or directory to upload")
+            .open(&mut open)
+            .collapsible(false)
+            .default_size((400.0, 400.0))
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Up").clicked() {
+                        new_dir = self.dir.parent().map(Path::to_path_buf);
+                    }
+                    ui.label(self.dir.display().to_string());
+                });
+                if ui.button("Upload this directory").clicked() {
+                    picked = Some(self.dir.clone());
+                }
+                ui.separator();
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    if let Some(error) = &self.error {
+                        ui.colored_label(egui::Color32::RED, error);
+                    }
+
+                    for entry in &self.entries {
+                        let label = if entry.is_dir {
+                            format!("[dir] {}", entry.name)
+                        } else {
+                            entry.name.clone()
+                        };
+
+                        if ui.selectable_label(false, label).clicked() {
+                            if entry.is_dir {
+                                new_dir = Some(entry.path.clone());
+                            } else {
+                                picked = Some(entry.path.clone());
+                            }
+                        }
+                    }
+                });
+            });
+
+        if let Some(dir) = new_dir {
+            self.change_dir(dir);
+        }
+
+        // Close the window once something was picked
+        self.open = open && picked.is_none();
+
+        picked
+    }
+    /// Show the given directory and read its entries
+    fn change_dir(&mut self, dir: PathBuf) {
+        self.entries.clear();
+        self.error = None;
+
+        match fs::read_dir(&dir) {
+            Ok(read_dir) => {
+                for entry in read_dir.flatten() {
+                    self.entries.push(Entry {
+                        name: entry.file_name().to_string_lossy().to_string(),
+                        path: entry.path(),
+                        // Follows symlinks, so a link to a directory can be opened
+                        is_dir: entry.path().is_dir(),
+                    });
+                }
+            }
+            Err(error) => self.error = Some(format!("Could not read {}: {error}", dir.display())),
+        }
+
+        // Directories first, then by name
+        self.entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+
+        self.dir = dir;
+    }
+}
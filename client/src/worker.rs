@@ -0,0 +1,320 @@
+use std::{fs, io::Write, path::{Component, Path}, sync::{mpsc, Arc}, thread, time::Duration};
+
+use eframe::egui;
+
+use file_share::{Share, ShareCommand, ShareCommandBuilder, ShareError, CommandType, Location, Progress, Connection, DIRECTORY_SUFFIX, is_partial};
+
+/// Contains a message the worker posts back to the UI
+pub enum Event {
+    /// A command was sent and the server replied, contains the command and the reply (or the error that stopped it)
+    Finished {
+        command: ShareCommand,
+        progress: Arc<Progress>,
+        result: Result<Share, ShareError>,
+        /// Uploads of files inside a directory that were refused because of a conflict, the rest of the directory was still sent
+        conflicts: Vec<ShareCommand>,
+    },
+}
+
+/// Owns the connection to the server and runs commands on a background thread, so the UI never waits on the network
+pub struct Worker {
+    /// Commands waiting to be sent to the server, along with the Progress of their file body
+    commands: mpsc::Sender<(ShareCommand, Arc<Progress>)>,
+    /// Events posted by the worker thread
+    events: mpsc::Receiver<Event>,
+}
+
+impl Worker {
+    /// Spawn the worker thread, the egui Context is used to wake the UI up whenever an event is posted. If resume is true UPLOAD
+    /// and RECEIVE commands pick up where an interrupted attempt stopped. UPLOAD and RECEIVE of a directory transfer every file in
+    /// it, one after the other. A PING is sent whenever no command was sent for the keepalive interval
+    pub fn spawn(mut stream: Connection, ctx: egui::Context, resume: bool, keepalive: Duration) -> Worker {
+        let (commands, command_receiver) = mpsc::channel::<(ShareCommand, Arc<Progress>)>();
+        let (event_sender, events) = mpsc::channel();
+
+        thread::spawn(move || {
+            // Commands are run one at a time in the order they were sent, this ends when the UI drops its Worker
+            loop {
+                let (command, progress) = match command_receiver.recv_timeout(keepalive) {
+                    Ok(command) => command,
+                    // Let the server know we are still here so it does not close the connection
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        let ping = ShareCommandBuilder::new().command_type(CommandType::Ping).build();
+                        if let Err(error) = send_to_server_receive_from_server(&mut stream, &ping, &Progress::new()) {
+                            eprintln!("Keepalive failed: {error}");
+                        }
+                        continue;
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                };
+
+                // Commands cancelled while waiting in the queue are never sent
+                let mut conflicts = Vec::new();
+                let result = if progress.is_cancelled() {
+                    Err(ShareError::Cancelled)
+                } else {
+                    run(&mut stream, &command, &progress, resume, &mut conflicts)
+                };
+
+                // The UI is gone, nobody is left to send commands
+                if event_sender.send(Event::Finished { command, progress, result, conflicts }).is_err() {
+                    break;
+                }
+
+                ctx.request_repaint();
+            }
+        });
+
+        Worker { commands, events }
+    }
+    /// Queue a command to be sent to the server, its result is posted as an Event. The progress of its file body (if it has one)
+    /// is shown through the given Progress, which can also be used to cancel it
+    pub fn send(&self, command: ShareCommand, progress: Arc<Progress>) {
+        // This only fails if the worker thread is gone, which only happens when the UI is closing
+        let _ = self.commands.send((command, progress));
+    }
+    /// Returns the events posted since the last call, this never blocks
+    pub fn poll(&self) -> Vec<Event> {
+        self.events.try_iter().collect()
+    }
+}
+
+/// Run a command on the server, directories given to UPLOAD or RECEIVE are transferred file by file. For a directory the reply to
+/// the command that created it is returned, uploads of files in it that were refused because of a conflict are added to conflicts
+fn run(
+    stream: &mut Connection,
+    command: &ShareCommand,
+    progress: &Arc<Progress>,
+    resume: bool,
+    conflicts: &mut Vec<ShareCommand>,
+) -> Result<Share, ShareError> {
+    if command.command_type() == Some(&CommandType::Upload) && command.local_path().is_some_and(Path::is_dir) {
+        return upload_tree(stream, command, progress, resume, conflicts);
+    }
+
+    let share = run_file(stream, command, progress, resume)?;
+
+    // The server lists what is in a directory instead of sending a body, the directory itself was created when the reply was
+    // executed
+    if let Some(listing) = share.directory_listing() {
+        let listing = listing.into_iter().map(String::from).collect::<Vec<_>>();
+        receive_tree(stream, command, &listing, progress, resume)?;
+    }
+
+    Ok(share)
+}
+
+/// Run a command that transfers at most one file, picking up where an interrupted attempt stopped if resume is true
+fn run_file(stream: &mut Connection, command: &ShareCommand, progress: &Arc<Progress>, resume: bool) -> Result<Share, ShareError> {
+    if resume {
+        let resumed = resume_offset(stream, command)?;
+
+        match send_to_server_receive_from_server(stream, &resumed, progress) {
+            // The file on the server shrank since the download was interrupted, what was kept can not be part of it
+            Err(ShareError::Server(error))
+                if matches!(*error, ShareError::Protocol(_)) &&
+                    resumed.command_type() == Some(&CommandType::Receive) &&
+                    resumed.offset().unwrap_or(0) > 0 =>
+            {
+                fs::remove_file(file_share::partial_path(command.local_path().unwrap()))?;
+                send_to_server_receive_from_server(stream, command, progress)
+            }
+            result => result,
+        }
+    } else {
+        send_to_server_receive_from_server(stream, command, progress)
+    }
+}
+
+/// Upload a local directory with everything in it, the layout is kept the same on the server. Files refused because of a conflict
+/// are skipped and added to conflicts, so the user can be asked about each of them without sending the others again
+fn upload_tree(
+    stream: &mut Connection,
+    command: &ShareCommand,
+    progress: &Arc<Progress>,
+    resume: bool,
+    conflicts: &mut Vec<ShareCommand>,
+) -> Result<Share, ShareError> {
+    let remote_root = command.arg().unwrap();
+    let local_root = command.local_path().unwrap();
+
+    let mkdir = |path: String| ShareCommandBuilder::new().command_type(CommandType::Mkdir).arg(path).build();
+
+    let share = run_file(stream, &mkdir(remote_root.to_string()), progress, resume)?;
+
+    for (relative, is_dir) in walk(local_root)? {
+        if progress.is_cancelled() {
+            return Err(ShareError::Cancelled);
+        }
+
+        let remote = format!("{}{DIRECTORY_SUFFIX}{relative}", remote_root.trim_end_matches(DIRECTORY_SUFFIX));
+        let command = if is_dir {
+            mkdir(remote)
+        } else {
+            let mut upload = ShareCommandBuilder::new()
+                .command_type(CommandType::Upload)
+                .arg(remote)
+                .local_path(local_root.join(&relative));
+            // Every file in the directory follows the policy picked for the directory
+            if let Some(conflict) = command.conflict() {
+                upload = upload.conflict(conflict);
+            }
+            upload.build()
+        };
+
+        match run_file(stream, &command, progress, resume) {
+            Ok(_) => (),
+            Err(error) if error.is_conflict() => conflicts.push(command),
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(share)
+}
+
+/// Receive every file the server listed in a directory, they are written to the same place inside the local directory
+fn receive_tree(
+    stream: &mut Connection,
+    command: &ShareCommand,
+    listing: &[String],
+    progress: &Arc<Progress>,
+    resume: bool,
+) -> Result<(), ShareError> {
+    let remote_root = command.arg().unwrap().trim_end_matches(DIRECTORY_SUFFIX);
+    let local_root = command.local_path().unwrap();
+
+    for entry in listing {
+        if progress.is_cancelled() {
+            return Err(ShareError::Cancelled);
+        }
+
+        let relative = entry.trim_end_matches(DIRECTORY_SUFFIX);
+        let local = local_root.join(local_name(relative)?);
+        if entry.ends_with(DIRECTORY_SUFFIX) {
+            fs::create_dir_all(local)?;
+            continue;
+        }
+
+        let command = ShareCommandBuilder::new()
+            .command_type(CommandType::Receive)
+            .arg(format!("{remote_root}{DIRECTORY_SUFFIX}{relative}"))
+            .local_path(local)
+            .build();
+        run_file(stream, &command, progress, resume)?;
+    }
+
+    Ok(())
+}
+
+/// Returns a name the server sent as a path relative to the local directory it is stored in. Names come from the server, so
+/// anything that could lead outside of that directory (`..`, an absolute path, an empty name) is refused
+pub fn local_name(name: &str) -> Result<&Path, ShareError> {
+    let path = Path::new(name);
+    let is_safe = path.components().next().is_some() &&
+        path.components().all(|component| matches!(component, Component::Normal(_)));
+
+    if is_safe {
+        Ok(path)
+    } else {
+        Err(ShareError::Protocol(format!("Server sent {name:?}, which is outside of the directory it is stored in")))
+    }
+}
+
+/// Returns the path (relative to the directory, separated with `/`) of everything in a local directory and true for the ones that
+/// are directories. Directories come before what is in them, symlinks to directories are skipped so a loop can not be followed
+fn walk(dir: &Path) -> Result<Vec<(String, bool)>, ShareError> {
+    let mut entries = Vec::new();
+    walk_into(dir, "", &mut entries)?;
+
+    Ok(entries)
+}
+
+fn walk_into(dir: &Path, prefix: &str, entries: &mut Vec<(String, bool)>) -> Result<(), ShareError> {
+    let mut dir_entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    dir_entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in dir_entries {
+        let relative = format!("{prefix}{}", entry.file_name().to_string_lossy());
+
+        if entry.file_type()?.is_dir() {
+            entries.push((relative.clone(), true));
+            walk_into(&entry.path(), &format!("{relative}{DIRECTORY_SUFFIX}"), entries)?;
+        } else if entry.path().is_file() && !is_partial(&entry.path()) {
+            // Partial files are what is left of interrupted downloads, the server would refuse them anyway
+            entries.push((relative, false));
+        }
+    }
+
+    Ok(())
+}
+
+/// Send a command to the server, then read and execute its reply. Errors reported by the server are returned as a
+/// ShareError::Server
+fn send_to_server_receive_from_server(stream: &mut Connection, command: &ShareCommand, progress: &Arc<Progress>) -> Result<Share, ShareError> {
+    let mut share = Share::new(command.clone(), Location::Client);
+    share.set_progress(Arc::clone(progress));
+    // Prepare data (if needed) for the specified command
+    share.prepare_data()?;
+
+    // Write the share we prepared to the server/stream
+    if let Err(error) = share.write_to_stream(stream, Location::Client) {
+        // The server still replies to a cancelled upload (after removing what it received), read the reply so the next command
+        // is not mixed up with it. The server also stops uploads it refuses, its reply says why
+        if error == ShareError::Cancelled {
+            Share::read_from_stream(stream, Location::Client)?.server_result()?;
+        }
+
+        return Err(error);
+    }
+
+    // Make sure all buffered contents reach there destination
+    stream.flush()?;
+
+    // Read in the response the server send, this can contain requested files, text data, etc.
+    let mut share = Share::read_from_stream(stream, Location::Client)?;
+    // The local path and Progress are not sent to the server, so they are missing from the reply
+    if let Some(local_path) = command.local_path() {
+        share.set_local_path(local_path.to_path_buf());
+    }
+    share.set_progress(Arc::clone(progress));
+
+    // Execute the command, this is where received files are written to storage
+    if let Err(error) = share.execute(stream) {
+        // A file body that was not read has to be thrown away before the next command
+        share.discard_body(stream)?;
+        return Err(error);
+    }
+    share.server_result()?;
+
+    Ok(share)
+}
+
+/// Returns the command with its offset set to where an earlier UPLOAD or RECEIVE of the same file stopped, so only the rest of the
+/// file is sent. Other commands are returned as is
+fn resume_offset(stream: &mut Connection, command: &ShareCommand) -> Result<ShareCommand, ShareError> {
+    let mut command = command.clone();
+
+    let offset = match command.command_type() {
+        // Interrupted downloads are kept next to the file on this side
+        Some(CommandType::Receive) => file_share::partial_size(command.local_path().unwrap())?,
+        // Interrupted uploads are kept by the server
+        Some(CommandType::Upload) => {
+            let partial = ShareCommandBuilder::new()
+                .command_type(CommandType::Partial)
+                .arg(command.arg().unwrap().to_string())
+                .build();
+            let kept = send_to_server_receive_from_server(stream, &partial, &Progress::new())?
+                .command()
+                .offset()
+                .unwrap_or(0);
+
+            // The local file shrank since, what the server kept can not be part of it
+            let size = std::fs::metadata(command.local_path().unwrap())?.len();
+            if kept > size { 0 } else { kept }
+        }
+        _ => return Ok(command),
+    };
+
+    command.set_offset(offset);
+    Ok(command)
+}
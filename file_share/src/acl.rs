@@ -0,0 +1,182 @@
+use std::{fmt, path::{Component, Path, PathBuf}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::ShareError;
+
+/// User name of rules that apply to everyone, including clients that did not log in
+const EVERYONE: &str = "*";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// Contains what a user may do with a path
+pub enum Permission {
+    /// Receive and copy files
+    Read,
+    /// Upload, delete and move files, create and remove directories
+    Write,
+    /// See what is in a directory and the metadata of files
+    List,
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Permission::Read => write!(f, "read"),
+            Permission::Write => write!(f, "write"),
+            Permission::List => write!(f, "list"),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+/// Contains a single rule of an Acl, declared in Config.toml as a `[[server.acl]]` table
+pub struct AclRule {
+    /// Name of the user the rule is for, `*` for everyone
+    user: String,
+    /// Path under the storage root the rule covers, along with everything inside of it. An empty path covers the whole storage
+    #[serde(default)]
+    path: String,
+    /// What the user may do, anything not listed is denied
+    permissions: Vec<Permission>,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Contains the rules saying which users may read, write or list which paths in the storage. The rule with the longest path that
+/// covers the path being used decides, a rule for the user wins over a rule for everyone with the same path. Paths no rule covers
+/// are denied, unless there are no rules at all
+pub struct Acl {
+    rules: Vec<AclRule>,
+}
+
+impl Acl {
+    /// Create a new Acl from the given rules, with no rules everyone may do everything
+    pub fn new(rules: Vec<AclRule>) -> Acl {
+        Acl { rules }
+    }
+    /// Returns the amount of rules
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+    /// Returns true if there are no rules, everyone may do everything
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+    /// Returns true if a rule is for a named user and not for everyone, those only work when users log in
+    pub fn has_named_users(&self) -> bool {
+        self.rules.iter().any(|rule| rule.user != EVERYONE)
+    }
+    /// Returns ShareError::PermissionDenied if the user (None if they did not log in) may not use the given path in the storage
+    /// the given way
+    pub fn check(&self, user: Option<&str>, path: &str, permission: Permission) -> Result<(), ShareError> {
+        if self.rules.is_empty() {
+            return Ok(());
+        }
+
+        let path = normalize(path);
+
+        let rule = self.rules.iter()
+            .filter(|rule| rule.user == EVERYONE || Some(rule.user.as_str()) == user)
+            .filter(|rule| path.starts_with(normalize(&rule.path)))
+            // Longest path first, then rules for the user over rules for everyone
+            .max_by_key(|rule| (normalize(&rule.path).components().count(), rule.user != EVERYONE));
+
+        match rule {
+            Some(rule) if rule.permissions.contains(&permission) => Ok(()),
+            _ => Err(ShareError::PermissionDenied {
+                user: user.map(String::from),
+                permission,
+                path: path.to_string_lossy().to_string(),
+            }),
+        }
+    }
+}
+
+/// Returns the path with `.` components and leading or trailing slashes taken out, so `./docs/` and `docs` are the same path.
+/// Paths are checked by Storage::resolve() before anything is done with them, `..` never gets here
+fn normalize(path: &str) -> PathBuf {
+    Path::new(path).components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(user: &str, path: &str, permissions: &[Permission]) -> AclRule {
+        AclRule { user: user.to_string(), path: path.to_string(), permissions: permissions.to_vec() }
+    }
+
+    #[test]
+    fn no_rules_allow_everything() {
+        let acl = Acl::new(Vec::new());
+
+        assert!(acl.check(None, "anything", Permission::Write).is_ok());
+    }
+
+    #[test]
+    fn paths_no_rule_covers_are_denied() {
+        let acl = Acl::new(vec![rule("*", "public", &[Permission::Read])]);
+
+        assert!(acl.check(None, "public/file.txt", Permission::Read).is_ok());
+        assert!(acl.check(None, "private/file.txt", Permission::Read).is_err());
+        assert!(acl.check(None, "public/file.txt", Permission::Write).is_err());
+    }
+
+    #[test]
+    fn rules_cover_whole_components() {
+        let acl = Acl::new(vec![rule("*", "public", &[Permission::Read])]);
+
+        assert!(acl.check(None, "public", Permission::Read).is_ok());
+        assert!(acl.check(None, "./public/", Permission::Read).is_ok());
+        assert!(acl.check(None, "publicity.txt", Permission::Read).is_err());
+        assert!(acl.check(None, "public2/file.txt", Permission::Read).is_err());
+    }
+
+    #[test]
+    fn longest_path_wins() {
+        let acl = Acl::new(vec![
+            rule("*", "", &[Permission::Read, Permission::List]),
+            rule("*", "private", &[]),
+            rule("*", "private/shared", &[Permission::Read]),
+        ]);
+
+        assert!(acl.check(None, "file.txt", Permission::Read).is_ok());
+        assert!(acl.check(None, "private/file.txt", Permission::Read).is_err());
+        assert!(acl.check(None, "private/shared/file.txt", Permission::Read).is_ok());
+        assert!(acl.check(None, "private/shared/file.txt", Permission::List).is_err());
+    }
+
+    #[test]
+    fn user_rules_win_over_everyone_on_the_same_path() {
+        let acl = Acl::new(vec![
+            rule("*", "", &[Permission::Read]),
+            rule("alice", "", &[Permission::Read, Permission::Write]),
+            rule("bob", "", &[]),
+        ]);
+
+        assert!(acl.check(Some("alice"), "file.txt", Permission::Write).is_ok());
+        assert!(acl.check(Some("bob"), "file.txt", Permission::Read).is_err());
+        assert!(acl.check(Some("carol"), "file.txt", Permission::Read).is_ok());
+        assert!(acl.check(Some("carol"), "file.txt", Permission::Write).is_err());
+        assert!(acl.check(None, "file.txt", Permission::Write).is_err());
+    }
+
+    #[test]
+    fn everyone_rules_on_a_longer_path_win_over_user_rules() {
+        let acl = Acl::new(vec![
+            rule("alice", "", &[Permission::Read, Permission::Write]),
+            rule("*", "archive", &[Permission::Read]),
+        ]);
+
+        assert!(acl.check(Some("alice"), "archive/old.txt", Permission::Write).is_err());
+        assert!(acl.check(Some("alice"), "archive/old.txt", Permission::Read).is_ok());
+    }
+
+    #[test]
+    fn named_users_are_found() {
+        assert!(!Acl::new(vec![rule("*", "", &[Permission::Read])]).has_named_users());
+        assert!(Acl::new(vec![rule("alice", "", &[Permission::Read])]).has_named_users());
+    }
+}
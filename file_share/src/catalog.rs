@@ -0,0 +1,255 @@
+use std::{fs, path::Path, time::UNIX_EPOCH};
+
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+
+use crate::{Checksum, Permission, ShareError, options::{CommandOptions, unknown_option}};
+
+/// Most entries a single page of the catalog holds, unless the server is set up differently
+pub const DEFAULT_CATALOG_PAGE_SIZE: u64 = 1000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// Contains what kind of thing a CatalogEntry is, directories sort before files
+pub enum EntryKind {
+    Directory,
+    File,
+    Symlink,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// Contains a single entry of the catalog along with its metadata
+pub struct CatalogEntry {
+    /// Name of the entry inside the directory that was listed
+    pub name: String,
+    pub kind: EntryKind,
+    /// Size in bytes, zero for directories
+    pub size: u64,
+    /// When the entry was last changed in seconds since the Unix epoch, None if the platform does not keep track of it
+    pub modified: Option<u64>,
+    /// What the user that asked for the catalog may do with the entry
+    pub permissions: Vec<Permission>,
+    /// Checksum of the file, only filled in when the server is set up to hash the files it lists and the user may read the file
+    pub checksum: Option<Checksum>,
+}
+
+impl CatalogEntry {
+    /// Read the metadata of the entry at the given path, symlinks are not followed. Permissions and checksum are left empty
+    pub(crate) fn read(path: &Path, name: String) -> Result<CatalogEntry, ShareError> {
+        let metadata = fs::symlink_metadata(path)?;
+
+        let kind = if metadata.is_dir() {
+            EntryKind::Directory
+        } else if metadata.is_symlink() {
+            EntryKind::Symlink
+        } else {
+            EntryKind::File
+        };
+        let modified = metadata.modified().ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| modified.as_secs());
+
+        Ok(CatalogEntry {
+            name,
+            kind,
+            size: if kind == EntryKind::Directory { 0 } else { metadata.len() },
+            modified,
+            permissions: Vec::new(),
+            checksum: None,
+        })
+    }
+    /// Returns true if the entry is a directory
+    pub fn is_dir(&self) -> bool {
+        self.kind == EntryKind::Directory
+    }
+    /// Returns true if the user that asked for the catalog has the given permission on the entry
+    pub fn allows(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Contains what the entries of the catalog are sorted by, entries that are the same are sorted by name
+pub enum CatalogSort {
+    #[default]
+    Name,
+    Kind,
+    Size,
+    Modified,
+}
+
+impl CatalogSort {
+    /// Every way the catalog can be sorted
+    pub const ALL: [CatalogSort; 4] = [CatalogSort::Name, CatalogSort::Kind, CatalogSort::Size, CatalogSort::Modified];
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+/// Contains which entries of a directory CATALOG returns and in what order. A large directory is returned in pages, the cursor of
+/// each page is the offset of the next one
+pub struct CatalogQuery {
+    /// Only entries with a name matching this are listed, see NameFilter
+    pub filter: Option<String>,
+    pub sort: CatalogSort,
+    /// Sort from high to low
+    pub descending: bool,
+    /// Amount of entries to skip
+    pub offset: u64,
+    /// Max amount of entries to return, the server never returns more than its own page size. None for a whole page
+    pub limit: Option<u64>,
+}
+
+impl CatalogQuery {
+    /// Take the options of a CATALOG command out of its arguments, like `--filter *.txt --sort size --desc --offset 100 --limit 50`.
+    /// Returns the arguments that are left
+    pub(crate) fn parse_options(args: Vec<String>) -> Result<(Vec<String>, CatalogQuery), ShareError> {
+        let mut query = CatalogQuery::default();
+        let mut options = CommandOptions::new(args);
+
+        while let Some(option) = options.next_option() {
+            match option.as_str() {
+                "--filter" => query.filter = Some(options.value(&option)?),
+                "--sort" => {
+                    let sort = options.value(&option)?;
+                    query.sort = CatalogSort::ALL.into_iter()
+                        .find(|candidate| format!("{candidate:?}").eq_ignore_ascii_case(&sort))
+                        .ok_or_else(|| ShareError::Parse(format!("Can not sort by {sort}, use name, kind, size or modified")))?;
+                }
+                "--desc" => query.descending = true,
+                "--offset" => query.offset = options.number(&option)?,
+                "--limit" => query.limit = Some(options.number(&option)?),
+                _ => return Err(unknown_option(&option)),
+            }
+        }
+
+        Ok((options.rest(), query))
+    }
+    /// Sort the entries and return the page the query asks for, it holds at most page_size entries
+    pub(crate) fn page(&self, mut entries: Vec<CatalogEntry>, page_size: u64) -> CatalogPage {
+        entries.sort_by(|a, b| {
+            let ordering = match self.sort {
+                CatalogSort::Name => a.name.cmp(&b.name),
+                CatalogSort::Kind => a.kind.cmp(&b.kind),
+                CatalogSort::Size => a.size.cmp(&b.size),
+                CatalogSort::Modified => a.modified.cmp(&b.modified),
+            }
+            .then_with(|| a.name.cmp(&b.name));
+
+            if self.descending { ordering.reverse() } else { ordering }
+        });
+
+        CatalogPage::slice(entries, self.offset, self.limit, page_size)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+/// Contains a page of the catalog
+pub struct CatalogPage {
+    pub entries: Vec<CatalogEntry>,
+    /// Amount of entries in the whole directory that match the filter
+    pub total: u64,
+    /// Offset of the next page, None if this is the last one
+    pub cursor: Option<u64>,
+}
+
+impl CatalogPage {
+    /// Returns the page of the sorted entries that starts at offset and holds at most limit entries, but never more than page_size
+    pub(crate) fn slice(entries: Vec<CatalogEntry>, offset: u64, limit: Option<u64>, page_size: u64) -> CatalogPage {
+        let total = entries.len() as u64;
+        let limit = limit.map_or(page_size, |limit| limit.min(page_size));
+        let end = offset.saturating_add(limit).min(total);
+
+        let entries = entries.into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect();
+
+        CatalogPage { entries, total, cursor: (end < total).then_some(end) }
+    }
+}
+
+/// Matches names against a filter. A filter with `*`, `?` or `[` in it is a glob pattern (like `*.txt`), anything else matches
+/// names that contain it, ignoring case
+pub(crate) enum NameFilter {
+    Glob(Pattern),
+    Contains(String),
+}
+
+impl NameFilter {
+    pub(crate) fn new(filter: &str) -> Result<NameFilter, ShareError> {
+        if filter.contains(['*', '?', '[']) {
+            Pattern::new(filter)
+                .map(NameFilter::Glob)
+                .map_err(|error| ShareError::Parse(format!("{filter} is not a valid pattern: {error}")))
+        } else {
+            Ok(NameFilter::Contains(filter.to_lowercase()))
+        }
+    }
+    pub(crate) fn matches(&self, name: &str) -> bool {
+        match self {
+            NameFilter::Glob(pattern) => pattern.matches(name),
+            NameFilter::Contains(part) => name.to_lowercase().contains(part),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<CatalogEntry> {
+        ["e", "a", "d", "c", "b"].into_iter().enumerate()
+            .map(|(size, name)| CatalogEntry {
+                name: name.to_string(),
+                kind: EntryKind::File,
+                size: size as u64,
+                modified: None,
+                permissions: Vec::new(),
+                checksum: None,
+            })
+            .collect()
+    }
+
+    fn names(page: &CatalogPage) -> Vec<&str> {
+        page.entries.iter().map(|entry| entry.name.as_str()).collect()
+    }
+
+    #[test]
+    fn pages_are_followed_by_their_cursor() {
+        let mut query = CatalogQuery::default();
+        let mut pages = Vec::new();
+
+        loop {
+            let page = query.page(entries(), 2);
+            assert_eq!(page.total, 5);
+            pages.push(names(&page).join(""));
+
+            match page.cursor {
+                Some(cursor) => query.offset = cursor,
+                None => break,
+            }
+        }
+
+        assert_eq!(pages, ["ab", "cd", "e"]);
+    }
+
+    #[test]
+    fn limit_is_capped_at_the_page_size() {
+        let query = CatalogQuery { limit: Some(10), ..CatalogQuery::default() };
+        let page = query.page(entries(), 3);
+        assert_eq!((names(&page), page.cursor), (vec!["a", "b", "c"], Some(3)));
+
+        let query = CatalogQuery { offset: 1, limit: Some(1), ..CatalogQuery::default() };
+        let page = query.page(entries(), 3);
+        assert_eq!((names(&page), page.cursor), (vec!["b"], Some(2)));
+
+        let query = CatalogQuery { offset: 10, ..CatalogQuery::default() };
+        let page = query.page(entries(), 3);
+        assert_eq!((page.entries.len(), page.cursor, page.total), (0, None, 5));
+    }
+
+    #[test]
+    fn pages_follow_the_sort_order() {
+        let query = CatalogQuery { sort: CatalogSort::Size, descending: true, ..CatalogQuery::default() };
+
+        assert_eq!(names(&query.page(entries(), 2)), ["b", "c"]);
+    }
+}
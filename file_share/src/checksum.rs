@@ -0,0 +1,87 @@
+use std::{fmt, fs::File, io::{self, Write}, path::Path};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Size (in bytes) of a checksum
+pub(crate) const CHECKSUM_LEN: usize = 32;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+/// Contains the SHA-256 checksum of a whole file. It is sent right after the file body so the receiver can confirm it ended up
+/// with the same file, even when the body was sent in parts over several connections
+pub struct Checksum([u8; CHECKSUM_LEN]);
+
+impl Checksum {
+    /// Create a Checksum from the bytes read off the stream
+    pub(crate) fn from_bytes(bytes: [u8; CHECKSUM_LEN]) -> Checksum {
+        Checksum(bytes)
+    }
+    /// Returns the bytes that are written to the stream
+    pub(crate) fn as_bytes(&self) -> &[u8; CHECKSUM_LEN] {
+        &self.0
+    }
+    /// Finish the given hasher
+    pub(crate) fn from_hasher(hasher: Sha256) -> Checksum {
+        Checksum(hasher.finalize().into())
+    }
+    /// Hash the whole file at the given path
+    pub(crate) fn of_file(path: &Path) -> io::Result<Checksum> {
+        let mut hasher = Sha256::new();
+        io::copy(&mut File::open(path)?, &mut hasher)?;
+
+        Ok(Checksum::from_hasher(hasher))
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Checksum({self})")
+    }
+}
+
+/// Hashes everything written through it before passing it on to the file
+pub(crate) struct HashingWriter {
+    file: File,
+    hasher: Sha256,
+}
+
+impl HashingWriter {
+    pub(crate) fn new(file: File) -> HashingWriter {
+        HashingWriter { file, hasher: Sha256::new() }
+    }
+    /// Hash what is already in the file without writing anything, used when a resumed body is appended to a partial file.
+    /// Returns the amount of bytes hashed
+    pub(crate) fn hash_existing(&mut self) -> io::Result<u64> {
+        io::copy(&mut &self.file, &mut self.hasher)
+    }
+    /// Flush everything that was written to disk
+    pub(crate) fn sync_all(&self) -> io::Result<()> {
+        self.file.sync_all()
+    }
+    /// Returns the checksum of everything that was hashed
+    pub(crate) fn finish(self) -> Checksum {
+        Checksum::from_hasher(self.hasher)
+    }
+}
+
+impl Write for HashingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.file.write(buf)?;
+        self.hasher.update(&buf[..len]);
+
+        Ok(len)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -1,7 +1,9 @@
-use std::fs;
+use std::{fs, time::Duration};
 
 use serde::Deserialize;
 
+use crate::{ShareLimits, ShareError, Credentials, Acl, AclRule, ConflictPolicy, DEFAULT_CATALOG_PAGE_SIZE};
+
 #[derive(Deserialize)]
 pub struct Config {
     pub server: Option<Server>,
@@ -11,13 +13,44 @@ pub struct Config {
 #[derive(Deserialize, PartialEq)]
 pub struct Server {
     thread_count: usize,
+    /// Addresses the server listens on, all of them at the same time
     ips: Vec<String>,
+    /// Directory the server stores its files in, defaults to the current directory
+    storage_root: Option<String>,
+    /// Directory uploads that fail their checksum are moved to, they are removed if this is not set
+    quarantine_dir: Option<String>,
+    /// Send the checksum of every file listed in the catalog, this reads every file each time the catalog is asked for
+    catalog_checksums: Option<bool>,
+    /// Most entries a single page of the catalog holds
+    catalog_page_size: Option<u64>,
+    /// What an upload does when a file is already at its path if the client did not pick a policy, overwrite if this is not set
+    upload_conflict: Option<ConflictPolicy>,
+    /// TOML file with the users that can log in, anyone can connect without logging in if this is not set
+    users_file: Option<String>,
+    /// Rules saying which users may read, write or list which paths, everyone may do everything if this is not set
+    acl: Option<Vec<AclRule>>,
 
     max_share_size_without_file: Option<u64>,
     max_file_size: Option<u64>,
 
     return_on_success: Option<String>,
     return_on_help: Option<String>,
+
+    /// Capabilities the server will not agree to during the handshake
+    disabled_capabilities: Option<Vec<String>>,
+
+    /// How long (in seconds) running commands get to finish when the server is stopped, before their connections are closed
+    shutdown_timeout: Option<u64>,
+    /// How long (in seconds) a single read or write on a connection may take before the connection is closed, 0 for no limit
+    read_timeout: Option<u64>,
+    write_timeout: Option<u64>,
+    /// How long (in seconds) the server waits for the next command before closing the connection, 0 for no limit
+    idle_timeout: Option<u64>,
+
+    /// PEM file with the certificate chain of the server, connections are encrypted with TLS when this and tls_key are set
+    tls_cert: Option<String>,
+    /// PEM file with the private key of tls_cert
+    tls_key: Option<String>,
 }
 
 #[derive(Deserialize, PartialEq)]
@@ -26,25 +59,45 @@ pub struct Client {
 
     retry_delay: u64,
     retry_amount: usize,
+
+    /// How often (in milliseconds) the catalog is refreshed on its own
+    catalog_refresh_delay: Option<u64>,
+    /// How long (in milliseconds) the connection may be quiet before a PING is sent, so the server does not close it
+    keepalive_interval: Option<u64>,
+
+    /// Capabilities the client will not ask for during the handshake
+    disabled_capabilities: Option<Vec<String>>,
+
+    /// PEM file with the CA that signed the certificate of the server, the connection is encrypted with TLS when this or
+    /// tls_fingerprint is set
+    tls_ca: Option<String>,
+    /// SHA-256 fingerprint of the certificate of the server, used to trust a self-signed certificate
+    tls_fingerprint: Option<String>,
+    /// Name the certificate of the server was issued for, defaults to the host part of server
+    tls_server_name: Option<String>,
+
+    /// User name and password sent to the server right after connecting
+    user: Option<String>,
+    password: Option<String>,
 }
 
 impl Config {
-    pub fn build(path: &str) -> Result<Config, Box<dyn std::error::Error>> {
+    pub fn build(path: &str) -> Result<Config, ShareError> {
         Ok(toml::from_str(&fs::read_to_string(path)?)?)
     }
-    pub fn server(self) -> Result<Server, Box<dyn std::error::Error>> {
+    pub fn server(self) -> Result<Server, ShareError> {
         if let Some(server) = self.server {
             return Ok(server);
         } 
 
-        Err("Server configuration is empty".into())
+        Err(ShareError::Config(String::from("Server configuration is empty")))
     }
-    pub fn client(self) -> Result<Client, Box<dyn std::error::Error>> {
+    pub fn client(self) -> Result<Client, ShareError> {
         if let Some(client) = self.client {
             return Ok(client);
         } 
 
-        Err("Client configuration is empty".into())
+        Err(ShareError::Config(String::from("Client configuration is empty")))
     }
 }
 
@@ -52,12 +105,81 @@ impl Server {
     pub fn thread_count(&self) -> usize {
         self.thread_count
     }
-    pub fn ip(&self) -> &str {
-        &self.ips[0]
-    }
-    pub fn ip_backups(&self) -> &Vec<String> {
+    /// Returns the addresses the server listens on
+    pub fn ips(&self) -> &[String] {
         &self.ips
     }
+    /// Returns the directory the server stores its files in
+    pub fn storage_root(&self) -> &str {
+        self.storage_root.as_deref().unwrap_or(".")
+    }
+    /// Returns the directory uploads that fail their checksum are moved to
+    pub fn quarantine_dir(&self) -> Option<&str> {
+        self.quarantine_dir.as_deref()
+    }
+    /// Returns true if the checksum of every file listed in the catalog is sent
+    pub fn catalog_checksums(&self) -> bool {
+        self.catalog_checksums.unwrap_or(false)
+    }
+    /// Returns the most entries a single page of the catalog holds
+    pub fn catalog_page_size(&self) -> u64 {
+        self.catalog_page_size.unwrap_or(DEFAULT_CATALOG_PAGE_SIZE)
+    }
+    /// Returns what an upload does when a file is already at its path if the client did not pick a policy
+    pub fn upload_conflict(&self) -> ConflictPolicy {
+        self.upload_conflict.unwrap_or_default()
+    }
+    /// Returns the file with the users that can log in
+    pub fn users_file(&self) -> Option<&str> {
+        self.users_file.as_deref()
+    }
+    /// Returns the rules saying which users may read, write or list which paths
+    pub fn acl(&self) -> Acl {
+        Acl::new(self.acl.clone().unwrap_or_default())
+    }
+    /// Returns the capabilities the server will not agree to
+    pub fn disabled_capabilities(&self) -> &[String] {
+        self.disabled_capabilities.as_deref().unwrap_or_default()
+    }
+    /// Returns how long (in seconds) running commands get to finish when the server is stopped, defaults to 30 seconds
+    pub fn shutdown_timeout(&self) -> u64 {
+        self.shutdown_timeout.unwrap_or(30)
+    }
+    /// Returns how long a single read on a connection may take, defaults to 30 seconds. None if there is no limit
+    pub fn read_timeout(&self) -> Option<Duration> {
+        timeout(self.read_timeout.unwrap_or(30))
+    }
+    /// Returns how long a single write on a connection may take, defaults to 30 seconds. None if there is no limit
+    pub fn write_timeout(&self) -> Option<Duration> {
+        timeout(self.write_timeout.unwrap_or(30))
+    }
+    /// Returns how long the server waits for the next command, defaults to 5 minutes. None if there is no limit
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        timeout(self.idle_timeout.unwrap_or(300))
+    }
+    /// Returns the certificate and private key paths if TLS is turned on, both must be set
+    pub fn tls(&self) -> Result<Option<(&str, &str)>, ShareError> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Ok(Some((cert, key))),
+            (None, None) => Ok(None),
+            _ => Err(ShareError::Config(String::from("tls_cert and tls_key must be set together"))),
+        }
+    }
+    /// Returns the certificate path, even if TLS is not turned on yet
+    pub fn tls_cert(&self) -> Option<&str> {
+        self.tls_cert.as_deref()
+    }
+    /// Returns the private key path, even if TLS is not turned on yet
+    pub fn tls_key(&self) -> Option<&str> {
+        self.tls_key.as_deref()
+    }
+    /// Returns the size limits for Shares the server receives
+    pub fn limits(&self) -> ShareLimits {
+        ShareLimits {
+            max_share_size: self.max_share_size_without_file,
+            max_file_size: self.max_file_size,
+        }
+    }
 }
 
 impl Client {
@@ -70,4 +192,51 @@ impl Client {
     pub fn retry_delay(&self) -> u64 {
         self.retry_delay
     }
-}
\ No newline at end of file
+    /// Returns how often (in milliseconds) the catalog is refreshed, defaults to 5 seconds
+    pub fn catalog_refresh_delay(&self) -> u64 {
+        self.catalog_refresh_delay.unwrap_or(5000)
+    }
+    /// Returns how long (in milliseconds) the connection may be quiet before a PING is sent, defaults to 1 minute
+    pub fn keepalive_interval(&self) -> u64 {
+        self.keepalive_interval.unwrap_or(60_000)
+    }
+    /// Returns the capabilities the client will not ask for
+    pub fn disabled_capabilities(&self) -> &[String] {
+        self.disabled_capabilities.as_deref().unwrap_or_default()
+    }
+    /// Returns the credentials to log in with, None if no user is set
+    pub fn credentials(&self) -> Option<Credentials> {
+        self.user.as_ref().map(|user| Credentials {
+            user: user.clone(),
+            password: self.password.clone().unwrap_or_default(),
+        })
+    }
+    /// Returns true if the connection to the server is encrypted with TLS
+    pub fn uses_tls(&self) -> bool {
+        self.tls_ca.is_some() || self.tls_fingerprint.is_some()
+    }
+    /// Returns the path of the CA that is trusted
+    pub fn tls_ca(&self) -> Option<&str> {
+        self.tls_ca.as_deref()
+    }
+    /// Returns the fingerprint of the certificate that is trusted
+    pub fn tls_fingerprint(&self) -> Option<&str> {
+        self.tls_fingerprint.as_deref()
+    }
+    /// Returns the name the certificate of the server must be issued for
+    pub fn tls_server_name(&self) -> &str {
+        match &self.tls_server_name {
+            Some(name) => name,
+            // `host:port`, ipv6 hosts are written like `[::1]:port`
+            None => self.server.rsplit_once(':')
+                .map_or(self.server.as_str(), |(host, _)| host)
+                .trim_start_matches('[')
+                .trim_end_matches(']'),
+        }
+    }
+}
+
+/// Returns a timeout of the given amount of seconds, None for 0 which means there is no timeout
+fn timeout(seconds: u64) -> Option<Duration> {
+    (seconds > 0).then(|| Duration::from_secs(seconds))
+}
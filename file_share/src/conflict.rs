@@ -0,0 +1,109 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ShareError, DIRECTORY_SUFFIX, options::{CommandOptions, unknown_option}};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+/// Contains what happens when an upload goes to a path that already has a file
+pub enum ConflictPolicy {
+    /// Refuse the upload
+    Fail,
+    /// Replace the file
+    #[default]
+    Overwrite,
+    /// Store the upload next to the file, with a number added to its name like `report (1).pdf`
+    KeepBoth,
+    /// Replace the file if the uploaded file was changed after it, refuse the upload otherwise
+    OverwriteIfNewer,
+}
+
+impl ConflictPolicy {
+    /// Every policy, in the order they are offered to the user
+    pub const ALL: [ConflictPolicy; 4] = [
+        ConflictPolicy::Fail,
+        ConflictPolicy::Overwrite,
+        ConflictPolicy::KeepBoth,
+        ConflictPolicy::OverwriteIfNewer,
+    ];
+
+    /// Take the `--conflict policy` option of an UPLOAD out of its arguments. Returns the arguments that are left
+    pub(crate) fn parse_options(args: Vec<String>) -> Result<(Vec<String>, Option<ConflictPolicy>), ShareError> {
+        let mut policy = None;
+        let mut options = CommandOptions::new(args);
+
+        while let Some(option) = options.next_option() {
+            match option.as_str() {
+                "--conflict" => policy = Some(options.value(&option)?.parse()?),
+                _ => return Err(unknown_option(&option)),
+            }
+        }
+
+        Ok((options.rest(), policy))
+    }
+}
+
+impl fmt::Display for ConflictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConflictPolicy::Fail => write!(f, "fail"),
+            ConflictPolicy::Overwrite => write!(f, "overwrite"),
+            ConflictPolicy::KeepBoth => write!(f, "keep-both"),
+            ConflictPolicy::OverwriteIfNewer => write!(f, "overwrite-if-newer"),
+        }
+    }
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = ShareError;
+
+    fn from_str(policy: &str) -> Result<ConflictPolicy, ShareError> {
+        ConflictPolicy::ALL.into_iter()
+            .find(|known| known.to_string() == policy.to_lowercase())
+            .ok_or_else(|| ShareError::Parse(format!(
+                "Unknown conflict policy {policy}, expected one of {}",
+                ConflictPolicy::ALL.map(|known| known.to_string()).join(", "),
+            )))
+    }
+}
+
+/// Returns the path with a number added to the end of the file name, before the extension. `dir/report.pdf` becomes
+/// `dir/report (1).pdf`
+pub(crate) fn numbered_path(path: &str, number: u32) -> String {
+    let (dir, name) = match path.rsplit_once(DIRECTORY_SUFFIX) {
+        Some((dir, name)) => (format!("{dir}{DIRECTORY_SUFFIX}"), name),
+        None => (String::new(), path),
+    };
+
+    // A leading dot is part of the name (like `.bashrc`), not an extension
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => format!("{dir}{stem} ({number}).{extension}"),
+        _ => format!("{dir}{name} ({number})"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_go_before_the_extension() {
+        assert_eq!(numbered_path("report.pdf", 1), "report (1).pdf");
+        assert_eq!(numbered_path("dir/archive.tar.gz", 2), "dir/archive.tar (2).gz");
+        assert_eq!(numbered_path("dir/README", 3), "dir/README (3)");
+        assert_eq!(numbered_path("dir.d/.bashrc", 1), "dir.d/.bashrc (1)");
+    }
+
+    #[test]
+    fn policies_are_parsed_by_name() {
+        for policy in ConflictPolicy::ALL {
+            assert_eq!(policy.to_string().parse::<ConflictPolicy>(), Ok(policy));
+        }
+        assert_eq!("Keep-Both".parse::<ConflictPolicy>(), Ok(ConflictPolicy::KeepBoth));
+        assert!(matches!("rename".parse::<ConflictPolicy>(), Err(ShareError::Parse(_))));
+
+        let args = vec![String::from("--conflict"), String::from("fail"), String::from("file.txt")];
+        assert_eq!(ConflictPolicy::parse_options(args), Ok((vec![String::from("file.txt")], Some(ConflictPolicy::Fail))));
+    }
+}
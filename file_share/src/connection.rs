@@ -0,0 +1,104 @@
+use std::{io::{self, Read, Write}, net::{SocketAddr, TcpStream}};
+
+use rustls::{ClientConnection, ConnectionCommon, ServerConnection, SideData, StreamOwned};
+
+/// Contains the connection between a client and the server, this is either a plain TCP stream or one encrypted with TLS. Everything
+/// the library sends or receives goes through a Connection, so the rest of the code does not care which one is used
+pub enum Connection {
+    /// Nothing is encrypted
+    Plain(TcpStream),
+    /// Encrypted connection made by the client, see TlsConnector
+    TlsClient(Box<StreamOwned<ClientConnection, TcpStream>>),
+    /// Encrypted connection accepted by the server, see TlsAcceptor
+    TlsServer(Box<StreamOwned<ServerConnection, TcpStream>>),
+}
+
+impl Connection {
+    /// Returns the TCP stream the connection runs over
+    pub fn tcp(&self) -> &TcpStream {
+        match self {
+            Connection::Plain(stream) => stream,
+            Connection::TlsClient(tls) => &tls.sock,
+            Connection::TlsServer(tls) => &tls.sock,
+        }
+    }
+    /// Returns the address of the other side
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp().peer_addr()
+    }
+    /// Returns true if the connection is encrypted with TLS
+    pub fn is_encrypted(&self) -> bool {
+        !matches!(self, Connection::Plain(_))
+    }
+    /// Returns true if the other side sent something that has not been read yet. This never blocks
+    pub(crate) fn data_waiting(&mut self) -> io::Result<bool> {
+        match self {
+            Connection::Plain(stream) => tcp_data_waiting(stream),
+            Connection::TlsClient(tls) => tls_data_waiting(&mut tls.conn, &mut tls.sock),
+            Connection::TlsServer(tls) => tls_data_waiting(&mut tls.conn, &mut tls.sock),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(stream) => stream.read(buf),
+            Connection::TlsClient(tls) => tls.read(buf),
+            Connection::TlsServer(tls) => tls.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(stream) => stream.write(buf),
+            Connection::TlsClient(tls) => tls.write(buf),
+            Connection::TlsServer(tls) => tls.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Plain(stream) => stream.flush(),
+            Connection::TlsClient(tls) => tls.flush(),
+            Connection::TlsServer(tls) => tls.flush(),
+        }
+    }
+}
+
+/// Returns true if bytes are waiting on the TCP stream, without taking them off
+fn tcp_data_waiting(stream: &TcpStream) -> io::Result<bool> {
+    let mut byte = [0; 1];
+
+    stream.set_nonblocking(true)?;
+    let peeked = stream.peek(&mut byte);
+    stream.set_nonblocking(false)?;
+
+    match peeked {
+        // The connection was closed, which the next read or write will report
+        Ok(0) => Ok(false),
+        Ok(_) => Ok(true),
+        Err(error) if error.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+/// Returns true if decrypted bytes are waiting on the TLS connection. Records that are waiting on the TCP stream are read and
+/// decrypted, a record that has only partly arrived is kept until the rest of it does
+fn tls_data_waiting<Data: SideData>(conn: &mut ConnectionCommon<Data>, sock: &mut TcpStream) -> io::Result<bool> {
+    let state = conn.process_new_packets().map_err(io::Error::other)?;
+    if state.plaintext_bytes_to_read() > 0 {
+        return Ok(true);
+    }
+
+    if !tcp_data_waiting(sock)? {
+        return Ok(false);
+    }
+
+    // Something is waiting so this does not block
+    conn.read_tls(sock)?;
+    let state = conn.process_new_packets().map_err(io::Error::other)?;
+
+    Ok(state.plaintext_bytes_to_read() > 0)
+}
@@ -0,0 +1,158 @@
+use std::{fmt, io};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{LimitError, Checksum, Permission};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// Contains every error the file_share library can return. This is also sent inside of a ServerResponse so clients can match on
+/// what went wrong on the server. Variants are sent by their position, so changing them needs a new PROTOCOL_VERSION
+pub enum ShareError {
+    /// A command could not be parsed
+    Parse(String),
+    /// Reading or writing a file or stream failed
+    Io {
+        kind: IoErrorKind,
+        message: String,
+    },
+    /// A Share or message could not be serialized or deserialized
+    Serialization(String),
+    /// The other side sent something that does not follow the protocol, or the handshake failed
+    Protocol(String),
+    /// A Share or file body was larger than allowed
+    Limit(LimitError),
+    /// A received file does not match the file that was sent, it was removed or quarantined
+    Checksum {
+        /// Checksum the sender sent
+        expected: Checksum,
+        /// Checksum of what was received
+        actual: Checksum,
+    },
+    /// A path sent by the client was refused by the server
+    Path(String),
+    /// The configuration file could not be used
+    Config(String),
+    /// The TLS connection could not be set up, or the other side is not trusted
+    Tls(String),
+    /// Logging in failed, or a command was sent before logging in
+    Auth(String),
+    /// The ACL of the server does not let the user use the path this way
+    PermissionDenied {
+        /// None if the client did not log in
+        user: Option<String>,
+        permission: Permission,
+        path: String,
+    },
+    /// An upload went to a path that already has a file, and the conflict policy did not let it replace it
+    Conflict(String),
+    /// The transfer was cancelled by either side
+    Cancelled,
+    /// The server reported an error, contains the error the server ran into
+    Server(Box<ShareError>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+/// Contains the kind of I/O error, std::io::ErrorKind can not be sent so the kinds clients care about are copied here
+pub enum IoErrorKind {
+    NotFound,
+    PermissionDenied,
+    AlreadyExists,
+    UnexpectedEof,
+    ConnectionLost,
+    /// The other side did not send or take anything before the timeout of the connection passed
+    TimedOut,
+    Other,
+}
+
+impl fmt::Display for ShareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShareError::Parse(message) => write!(f, "Parse error: {message}"),
+            ShareError::Io { message, .. } => write!(f, "I/O error: {message}"),
+            ShareError::Serialization(message) => write!(f, "Serialization error: {message}"),
+            ShareError::Protocol(message) => write!(f, "Protocol error: {message}"),
+            ShareError::Limit(limit_error) => write!(f, "{limit_error}"),
+            ShareError::Checksum { expected, actual } => {
+                write!(f, "Checksum error: expected {expected} but received {actual}")
+            }
+            ShareError::Path(message) => write!(f, "Path error: {message}"),
+            ShareError::Config(message) => write!(f, "Config error: {message}"),
+            ShareError::Tls(message) => write!(f, "TLS error: {message}"),
+            ShareError::Auth(message) => write!(f, "Auth error: {message}"),
+            ShareError::PermissionDenied { user, permission, path } => {
+                let user = user.as_deref().unwrap_or("anonymous");
+                let path = if path.is_empty() { "/" } else { path };
+
+                write!(f, "Permission denied: {user} may not {permission} {path}")
+            }
+            ShareError::Conflict(message) => write!(f, "Conflict: {message}"),
+            ShareError::Cancelled => write!(f, "Transfer was cancelled"),
+            ShareError::Server(error) => write!(f, "Server error: {error}"),
+        }
+    }
+}
+
+impl ShareError {
+    /// Returns true if the error means a transfer was cancelled, on either side
+    pub fn is_cancelled(&self) -> bool {
+        match self {
+            ShareError::Cancelled => true,
+            ShareError::Server(error) => error.is_cancelled(),
+            _ => false,
+        }
+    }
+    /// Returns true if the error means an upload was refused because a file is already there, on either side
+    pub fn is_conflict(&self) -> bool {
+        match self {
+            ShareError::Conflict(_) => true,
+            ShareError::Server(error) => error.is_conflict(),
+            _ => false,
+        }
+    }
+}
+
+impl std::error::Error for ShareError {}
+
+impl From<io::Error> for ShareError {
+    fn from(error: io::Error) -> ShareError {
+        let kind = match error.kind() {
+            io::ErrorKind::NotFound => IoErrorKind::NotFound,
+            io::ErrorKind::PermissionDenied => IoErrorKind::PermissionDenied,
+            io::ErrorKind::AlreadyExists => IoErrorKind::AlreadyExists,
+            io::ErrorKind::UnexpectedEof => IoErrorKind::UnexpectedEof,
+            io::ErrorKind::ConnectionReset |
+            io::ErrorKind::ConnectionAborted |
+            io::ErrorKind::BrokenPipe => IoErrorKind::ConnectionLost,
+            // A socket with a timeout reports WouldBlock on unix and TimedOut on windows
+            io::ErrorKind::WouldBlock |
+            io::ErrorKind::TimedOut => IoErrorKind::TimedOut,
+            _ => IoErrorKind::Other,
+        };
+
+        ShareError::Io { kind, message: error.to_string() }
+    }
+}
+
+impl From<bincode::Error> for ShareError {
+    fn from(error: bincode::Error) -> ShareError {
+        ShareError::Serialization(error.to_string())
+    }
+}
+
+impl From<toml::de::Error> for ShareError {
+    fn from(error: toml::de::Error) -> ShareError {
+        ShareError::Config(error.to_string())
+    }
+}
+
+impl From<rustls::Error> for ShareError {
+    fn from(error: rustls::Error) -> ShareError {
+        ShareError::Tls(error.to_string())
+    }
+}
+
+impl From<LimitError> for ShareError {
+    fn from(error: LimitError) -> ShareError {
+        ShareError::Limit(error)
+    }
+}
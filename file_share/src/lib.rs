@@ -1,11 +1,67 @@
 #![feature(core_intrinsics)]
 
-use std::{process, fs::{File, self}, io::{Read, Write, BufReader, BufRead}, mem, net::TcpStream};
+use std::{
+    process, fs::{File, OpenOptions, self}, io::{self, Read, Write}, mem, fmt, ops::RangeInclusive, path::{Path, PathBuf}, sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
 
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
+mod error;
+pub use error::{ShareError, IoErrorKind};
 mod config;
 pub use config::Config;
+mod storage;
+pub use storage::Storage;
+use storage::UploadClaim;
+mod progress;
+pub use progress::Progress;
+mod protocol;
+pub use protocol::{Handshake, PROTOCOL_VERSION, MIN_PROTOCOL_VERSION, SUPPORTED_CAPABILITIES, RESUME};
+
+mod connection;
+pub use connection::Connection;
+
+mod tls;
+pub use tls::{TlsAcceptor, TlsConnector, generate_certificate, fingerprint};
+
+mod users;
+pub use users::{Users, User, Credentials, hash_password, login};
+
+mod acl;
+pub use acl::{Acl, AclRule, Permission};
+
+mod catalog;
+pub use catalog::{CatalogEntry, EntryKind, CatalogQuery, CatalogSort, CatalogPage, DEFAULT_CATALOG_PAGE_SIZE};
+use catalog::NameFilter;
+
+mod search;
+pub use search::{SearchQuery, parse_date};
+use search::SearchFilter;
+
+mod conflict;
+pub use conflict::ConflictPolicy;
+use conflict::numbered_path;
+
+mod checksum;
+pub use checksum::Checksum;
+use checksum::{HashingWriter, CHECKSUM_LEN};
+
+mod options;
+#[cfg(test)]
+mod test_util;
+
+/// Size (in bytes) of each chunk a file body is split into when it is streamed
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Max length (in bytes) of a `length\n` header line
+const MAX_HEADER_LEN: usize = 20;
+/// Header line sent in place of a length to stop a file body early, or to ask the sender of a file body to stop
+const ABORT_HEADER: &[u8] = b"abort\n";
+/// Extension added to a file while its body is being received, it is renamed once the whole body arrived
+const PARTIAL_EXTENSION: &str = "part";
+/// Added to the end of directory names in a catalog or directory listing, so they can be told apart from files
+pub const DIRECTORY_SUFFIX: char = '/';
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
 /// Contains the type of the command
@@ -16,20 +72,59 @@ pub enum CommandType {
 
     // Runs on server
     Upload,
+    /// Receiving a directory gives a listing of everything in it instead of a file body, see Share::directory_listing()
     Receive,
+    /// Lists the directory given as argument, or the storage root without one
     Catalog,
+    Partial,
+    /// Sent with Credentials right after connecting, handled by the server before anything else
+    Login,
+    Delete,
+    /// Moves a file or directory, the first argument is the source and the second the destination
+    Rename,
+    /// Copies a file, the first argument is the source and the second the destination
+    Copy,
+    /// Creates a directory along with any missing parents
+    Mkdir,
+    /// Removes an empty directory
+    Rmdir,
+    /// Finds files in the directory given as argument (or the storage root) and everything below it
+    Search,
+    /// Returns the metadata of a single file or directory without its body, files the user may read come with their checksum. See
+    /// Share::stat()
+    Stat,
+    /// Does nothing, sent to keep a quiet connection from hitting the idle timeout of the server
+    Ping,
 }
 
 impl CommandType {
-    /// Returns true if the CommandType has an argument
-    fn has_arg(&self) -> bool {
-        if *self == CommandType::Exit ||
-           *self == CommandType::Help ||
-           *self == CommandType::Catalog 
-        {
-            false
-        } else {
-            true
+    /// Returns the amount of arguments the CommandType takes
+    fn arg_count(&self) -> RangeInclusive<usize> {
+        match self {
+            CommandType::Exit | CommandType::Help | CommandType::Login | CommandType::Ping => 0..=0,
+            CommandType::Catalog | CommandType::Search => 0..=1,
+            CommandType::Rename | CommandType::Copy => 2..=2,
+            _ => 1..=1,
+        }
+    }
+    /// Returns an error if the CommandType does not take the given amount of arguments
+    fn check_args(&self, found: usize) -> Result<(), ShareError> {
+        match (self.arg_count(), found) {
+            (expected, found) if expected.contains(&found) => Ok(()),
+            // Arguments provided with command, but command does not use an argument
+            (expected, _) if *expected.end() == 0 => {
+                Err(ShareError::Parse(format!("{self:?} does not have an argument")))
+            }
+            // Command requires an argument
+            (_, 0) => Err(ShareError::Parse(String::from("No argument provided for command"))),
+            (expected, found) if expected.start() == expected.end() => {
+                Err(ShareError::Parse(format!("{self:?} takes {} arguments, got {found}", expected.start())))
+            }
+            (expected, found) => Err(ShareError::Parse(format!(
+                "{self:?} takes {} to {} arguments, got {found}",
+                expected.start(),
+                expected.end(),
+            ))),
         }
     }
     /// Returns true if the command runs on the client side
@@ -48,71 +143,142 @@ impl CommandType {
 /// Structure contains all data for a Command, the type of command and arguments provided with the command
 pub struct ShareCommand {
     command_type: Option<CommandType>,
-    arg: Option<String>,
+    args: Vec<String>,
+    #[serde(skip)]
+    /// Path of the file on the client, the file is read from here on UPLOAD and written here on RECEIVE. This is never sent to the
+    /// server, if it is None the first argument is used
+    local_path: Option<PathBuf>,
+    /// Byte offset the file body of an UPLOAD or RECEIVE starts at, used to resume an interrupted transfer. The reply to PARTIAL
+    /// has this set to the size of the interrupted upload the server kept
+    offset: Option<u64>,
+    /// Which entries CATALOG returns and in what order, None for the first page sorted by name
+    query: Option<CatalogQuery>,
+    /// What SEARCH looks for, None for every file
+    search: Option<SearchQuery>,
+    /// What an UPLOAD does when a file is already at its path, None for the default of the server
+    conflict: Option<ConflictPolicy>,
 }
 
 impl ShareCommand {
     // TODO: allow ability to use different command parser
-    /// Parse a &str into a ShareCommand structure
-    pub fn parse(command: &str) -> Result<ShareCommand, Box<dyn std::error::Error>> {
-        // Check if the command is empty
-        if command.is_empty() {
-            return Err("Parse error: Empty command".into());
-        }
+    /// Parse a &str into a ShareCommand structure. Arguments are separated by whitespace, an argument with spaces in it can be
+    /// put in single or double quotes like `RENAME "old name.txt" new.txt`. CATALOG, SEARCH and UPLOAD take options as well, see
+    /// CatalogQuery::parse_options(), SearchQuery::parse_options() and ConflictPolicy::parse_options()
+    pub fn parse(command: &str) -> Result<ShareCommand, ShareError> {
+        // Split the command into words, keeping quoted words together
+        let mut command_tokens = split_args(command)?.into_iter();
 
-        // Create an iterator over each word
-        let mut command_tokens = command.split_whitespace();
+        // Check if the command is empty
+        let command_type = match command_tokens.next() {
+            Some(command_type) => command_type,
+            None => return Err(ShareError::Parse(String::from("Empty command"))),
+        };
 
         // Check the type
-        let command_type = match command_tokens.next().unwrap() {
+        let command_type = match command_type.as_str() {
             "EXIT" => CommandType::Exit,
             "HELP" => CommandType::Help,
 
             "UPLOAD" => CommandType::Upload,
             "RECEIVE" => CommandType::Receive,
             "CATALOG" => CommandType::Catalog,
+            "PARTIAL" => CommandType::Partial,
+            "DELETE" => CommandType::Delete,
+            "RENAME" | "MOVE" => CommandType::Rename,
+            "COPY" => CommandType::Copy,
+            "MKDIR" => CommandType::Mkdir,
+            "RMDIR" => CommandType::Rmdir,
+            "SEARCH" => CommandType::Search,
+            "STAT" => CommandType::Stat,
+            "PING" => CommandType::Ping,
 
             unknown => {
                 return Err(
-                    format!(
-                        "Parse error: Unknow command type: {unknown}",
-                    ).into()
+                    ShareError::Parse(format!(
+                        "Unknow command type: {unknown}",
+                    ))
                 );
             }
         };
 
-        let arg: Option<String> = match command_tokens.next() {
-            // Command uses argument and the argument was found
-            Some(arg) if command_type.has_arg() => Some(arg.to_string()),
-            // Argument provided with command, but command does not use an argument
-            Some(_) if !command_type.has_arg() => {
-                return Err(
-                    format!(
-                        "Parse Error: {:?} does not have an argument",
-                        command_type,
-                    ).into()
-                );
-            }
-            // Command requires an argument
-            None if command_type.has_arg() => {
-                return Err("Parse error: No argument provided for command".into());
-            },
-            // Command does not require an argument
-            None if !command_type.has_arg() => None,
+        let mut args: Vec<String> = command_tokens.collect();
 
-            _ => return Err("Parse error: Unknown".into()),
-        };
+        let mut query = None;
+        let mut search = None;
+        let mut conflict = None;
+        if command_type == CommandType::Catalog {
+            let (rest, catalog_query) = CatalogQuery::parse_options(args)?;
+            args = rest;
+            query = Some(catalog_query);
+        } else if command_type == CommandType::Search {
+            let (rest, search_query) = SearchQuery::parse_options(args)?;
+            args = rest;
+            search = Some(search_query);
+        } else if command_type == CommandType::Upload {
+            (args, conflict) = ConflictPolicy::parse_options(args)?;
+        }
+
+        command_type.check_args(args.len())?;
 
         // Return parsed command
         Ok(ShareCommand { 
             command_type: Some(command_type), 
-            arg, 
+            args, 
+            local_path: None,
+            offset: None,
+            query,
+            search,
+            conflict,
         })
     }
+    /// Returns an error if the command has no CommandType or the wrong amount of arguments for it. parse() already checks this,
+    /// the server checks it again since a client does not have to use parse()
+    pub fn validate(&self) -> Result<(), ShareError> {
+        match self.command_type {
+            Some(command_type) => command_type.check_args(self.args.len()),
+            None => Err(ShareError::Protocol(String::from("No command type was sent"))),
+        }
+    }
     /// Returns the CommandType of self
     pub fn command_type(&self) -> Option<&CommandType> {
         self.command_type.as_ref()
     }
+    /// Returns the first argument of self
+    pub fn arg(&self) -> Option<&str> {
+        self.args.first().map(String::as_str)
+    }
+    /// Returns every argument of self
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+    /// Returns the path of the file on the client, this is the first argument unless a local path was set
+    pub fn local_path(&self) -> Option<&Path> {
+        self.local_path.as_deref().or_else(|| self.arg().map(Path::new))
+    }
+    /// Returns the byte offset the file body starts at
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+    /// Set the byte offset the file body starts at, the first offset bytes of the file are not sent
+    pub fn set_offset(&mut self, offset: u64) {
+        self.offset = Some(offset);
+    }
+    /// Returns which entries CATALOG returns and in what order
+    pub fn query(&self) -> Option<&CatalogQuery> {
+        self.query.as_ref()
+    }
+    /// Returns what SEARCH looks for
+    pub fn search(&self) -> Option<&SearchQuery> {
+        self.search.as_ref()
+    }
+    /// Returns what an UPLOAD does when a file is already at its path, None for the default of the server
+    pub fn conflict(&self) -> Option<ConflictPolicy> {
+        self.conflict
+    }
+    /// Returns true if the command needs the resume capability
+    pub fn uses_resume(&self) -> bool {
+        self.offset.is_some_and(|offset| offset > 0) || self.command_type == Some(CommandType::Partial)
+    }
     pub fn command_type_is(&mut self, cmp: CommandType) -> bool {
         if let Some(command_type) = self.command_type {
             if command_type == cmp {
@@ -129,23 +295,70 @@ impl ShareCommand {
 #[derive(Debug)]
 pub struct ShareCommandBuilder {
     command_type: Option<CommandType>,
-    arg: Option<String>,
+    args: Vec<String>,
+    local_path: Option<PathBuf>,
+    offset: Option<u64>,
+    query: Option<CatalogQuery>,
+    search: Option<SearchQuery>,
+    conflict: Option<ConflictPolicy>,
 }
 
 impl ShareCommandBuilder {
     pub fn new() -> ShareCommandBuilder {
-        ShareCommandBuilder { command_type: None, arg: None }
+        ShareCommandBuilder {
+            command_type: None,
+            args: Vec::new(),
+            local_path: None,
+            offset: None,
+            query: None,
+            search: None,
+            conflict: None,
+        }
     }
     pub fn command_type(mut self, command_type: CommandType) -> ShareCommandBuilder {
         self.command_type = Some(command_type);
         self
     }
+    /// Add an argument after the ones that were already added
     pub fn arg(mut self, arg: String) -> ShareCommandBuilder {
-        self.arg = Some(arg);
+        self.args.push(arg);
+        self
+    }
+    /// Set the path of the file on the client when it is different from the arg
+    pub fn local_path(mut self, local_path: PathBuf) -> ShareCommandBuilder {
+        self.local_path = Some(local_path);
+        self
+    }
+    /// Set the byte offset the file body starts at, used to resume an interrupted transfer
+    pub fn offset(mut self, offset: u64) -> ShareCommandBuilder {
+        self.offset = Some(offset);
+        self
+    }
+    /// Set which entries CATALOG returns and in what order
+    pub fn query(mut self, query: CatalogQuery) -> ShareCommandBuilder {
+        self.query = Some(query);
+        self
+    }
+    /// Set what SEARCH looks for
+    pub fn search(mut self, search: SearchQuery) -> ShareCommandBuilder {
+        self.search = Some(search);
+        self
+    }
+    /// Set what an UPLOAD does when a file is already at its path
+    pub fn conflict(mut self, conflict: ConflictPolicy) -> ShareCommandBuilder {
+        self.conflict = Some(conflict);
         self
     }
     pub fn build(self) -> ShareCommand {
-        ShareCommand { command_type: self.command_type, arg: self.arg}
+        ShareCommand {
+            command_type: self.command_type,
+            args: self.args,
+            local_path: self.local_path,
+            offset: self.offset,
+            query: self.query,
+            search: self.search,
+            conflict: self.conflict,
+        }
     }
 }
 
@@ -162,8 +375,42 @@ pub struct Share {
     /// Contains the command in execution
     command: ShareCommand,
 
-    /// Contains file data
-    file: Option<Vec<u8>>,
+    /// Contains the size of the whole file, the body streamed right after this Share holds the bytes from the command offset to
+    /// here. None if no body follows
+    file_size: Option<u64>,
+    /// Contains when the file the body is streamed from was last changed (in seconds since the Unix epoch), the server gives
+    /// uploaded files this time
+    file_modified: Option<u64>,
+    #[serde(skip)]
+    /// Contains the file the body will be streamed from when this Share is written
+    file: Option<File>,
+    #[serde(skip)]
+    /// True if a file body follows this Share on the stream and has not been read yet
+    body_pending: bool,
+    #[serde(skip)]
+    /// Contains the max size of a file body this Share will accept, None if there is no limit
+    max_file_size: Option<u64>,
+    #[serde(skip)]
+    /// Contains the storage the server executes this Share in
+    storage: Storage,
+    #[serde(skip)]
+    /// Contains the progress of the file body, updated while it is sent or received
+    progress: Option<Arc<Progress>>,
+    #[serde(skip)]
+    /// Contains the rules the server checks the command against, None if everything is allowed
+    acl: Option<Arc<Acl>>,
+    #[serde(skip)]
+    /// Contains the user that sent the command, None if they did not log in
+    user: Option<String>,
+    /// Contains the checksum of the file that was received, the server sends it back after an UPLOAD so the client can show it
+    checksum: Option<Checksum>,
+    /// Contains the user name and password sent with LOGIN, the server takes them out before replying
+    credentials: Option<Credentials>,
+    /// Contains the page of the directory listed by CATALOG, or of the files SEARCH found. The name of a file SEARCH found is its
+    /// path relative to the directory that was searched
+    catalog: Option<CatalogPage>,
+    /// Contains the metadata of the path STAT was sent for, its name is the path as the client sent it
+    stat: Option<CatalogEntry>,
     /// Contains text data, this is interpretted diferent ways depending on the
     /// CommandType. This can be file names, the file catalogue, etc.
     pub text_data: Option<String>,
@@ -178,14 +425,32 @@ impl Share {
     pub fn new(command: ShareCommand, current_location: Location) -> Share {
         Share { 
             command, 
+            file_size: None,
+            file_modified: None,
             file: None,
+            body_pending: false,
+            max_file_size: None,
+            storage: Storage::default(),
+            progress: None,
+            acl: None,
+            user: None,
+            checksum: None,
+            credentials: None,
+            catalog: None,
+            stat: None,
             text_data: None, 
             server_response: ServerResponse::new(),
             current_location
         }
     }
-    /// Write self to the given stream, this handles all writing including sending the seperate header containing the size of self
-    pub fn write_to_stream(&mut self, stream: &mut TcpStream, current_location: Location) -> Result<(), Box<dyn std::error::Error>>{
+    /// Write self to the given stream, this handles all writing including sending the seperate header containing the size of self. If
+    /// a file was loaded into self (see prepare_data() and execute()) its body is streamed in chunks right after the Share
+    pub fn write_to_stream(&mut self, stream: &mut Connection, current_location: Location) -> Result<(), ShareError>{
+        // A body only follows the Share if there is a file to stream it from
+        if self.file.is_none() {
+            self.file_size = None;
+        }
+
         // Convert the share to bytes so it can be written to the stream
         let share = bincode::serialize(self)?;
 
@@ -193,7 +458,7 @@ impl Share {
         let content_len = mem::size_of_val(&share[..]);
 
         // Send a header containing the content length and a newline
-        stream.write(
+        stream.write_all(
         format!("{}\n",
                 content_len
             ).as_bytes()
@@ -202,50 +467,228 @@ impl Share {
         // Write the share to the stream
         stream.write_all(&share[..])?;
 
+        // Stream the file body (if there is one) right after the Share
+        self.write_body_to_stream(stream)?;
+
         // Set the current_location
         self.current_location = current_location;
 
         Ok(())
     }
     /// Read data from the given stream, this handles all the reading of the sent Share struct. Returns a Result<T, E> containing the 
-    /// recieved Share struct on success. Returns a Result<T, E> containing a Box<dyn std::error::Error> on failure, this can mean many
+    /// recieved Share struct on success. Returns a Result<T, E> containing a ShareError on failure, this can mean many
     /// things such as, failing to read the header, failing to parse the header, failing to read the send Share structure, and lastly
     /// failing to deserialize the Share structure
-    pub fn read_from_stream(stream: &mut TcpStream, current_location: Location) -> Result<Share, Box<dyn std::error::Error>> {
-        // Create an empty buffer, this will be used to read the header
-        let mut share_len: String = String::new() ;
-        // Wrap the stream in a buf reader
-        let mut buf_reader = BufReader::new(stream);
-    
-        // Read header, the header is formated like `content_length\n`
-        buf_reader.read_line(&mut share_len)?;
-
-        // Parse the header into a usize
-        let share_len: usize = share_len.trim().parse()?;
+    /// 
+    /// If the sent Share has a file body it is left on the stream, it must be read with read_body_from_stream() (execute() does this)
+    /// or thrown away with discard_body() before anything else is read from the stream
+    pub fn read_from_stream(stream: &mut Connection, current_location: Location) -> Result<Share, ShareError> {
+        Share::read_from_stream_limited(stream, current_location, ShareLimits::default())
+    }
+    /// Same as read_from_stream() but the given limits are checked before anything is allocated. If the Share is larger than 
+    /// limits.max_share_size a LimitError is returned and the Share is left on the stream. limits.max_file_size is checked against
+    /// the file body when the Share is executed
+    pub fn read_from_stream_limited(stream: &mut Connection, current_location: Location, limits: ShareLimits) -> Result<Share, ShareError> {
+        // Read header, the header is formated like `content_length\n`. An abort line here was sent after a file body had already
+        // ended, so there was nothing left to stop and it is skipped
+        let share_len = loop {
+            match read_header(stream)? {
+                Header::Length(len) => break len,
+                Header::Abort => continue,
+            }
+        };
+
+        // Check the size of the Share before allocating a buffer for it
+        limits.check(ShareLimit::ShareSize, share_len)?;
+
+        let share_len = share_len as usize;
 
         // Create a new buffer that will store the bytes of the Share
-        let mut share_bytes = Vec::new();
-        // Resize the buffer to the share_len so we can call read_exact()
-        share_bytes.resize(share_len, 0);
+        let mut share_bytes = vec![0; share_len];
         
         // Read all the bytes making up the send Share into the bufferr
-        buf_reader.read_exact(&mut share_bytes)?;
+        stream.read_exact(&mut share_bytes)?;
 
         // Convert the bytes back into a Share
         let mut share = bincode::deserialize::<Share>(&share_bytes[..])?;
 
         // Set the current_location
         share.current_location = current_location;
+        // The file body (if any) is still waiting on the stream
+        share.body_pending = share.file_size.is_some();
+        share.max_file_size = limits.max_file_size;
 
         Ok(share)
     }
+    /// Stream the file loaded into self to the given stream, starting at the command offset. The body is sent as chunks formated like
+    /// `chunk_length\n` followed by the chunk bytes, a chunk with a length of zero marks the end of the body and is followed by the
+    /// Checksum of the whole file. Only CHUNK_SIZE bytes are held in memory at a time. Returns the amount of bytes written, this is
+    /// zero if no file was loaded
+    /// 
+    /// If the transfer is cancelled (through its Progress, or by the other side sending `abort\n`) the body is ended with an
+    /// `abort\n` line instead and ShareError::Cancelled is returned
+    pub fn write_body_to_stream(&mut self, stream: &mut Connection) -> Result<u64, ShareError> {
+        let mut file = match self.file.take() {
+            Some(file) => file,
+            None => return Ok(0),
+        };
+
+        let offset = self.command.offset.unwrap_or(0);
+        if let Some(progress) = &self.progress {
+            progress.start(offset, self.file_size);
+        }
+
+        // The checksum covers the whole file, so the part the other side already has is hashed without being sent
+        let mut hasher = Sha256::new();
+        let skipped = io::copy(&mut (&mut file).take(offset), &mut hasher)?;
+        if skipped != offset {
+            return Err(ShareError::Protocol(format!("Offset {offset} is past the end of the file")));
+        }
+
+        let mut chunk = vec![0; CHUNK_SIZE];
+        // Each frame (header + chunk) is written with a single call
+        let mut frame = Vec::with_capacity(CHUNK_SIZE + MAX_HEADER_LEN);
+        let mut written = 0;
+
+        loop {
+            // Stop between chunks if we or the other side want to
+            let cancelled = self.progress.as_ref().is_some_and(|progress| progress.is_cancelled());
+            if cancelled || abort_requested(stream)? {
+                stream.write_all(ABORT_HEADER)?;
+                return Err(ShareError::Cancelled);
+            }
+
+            let len = match file.read(&mut chunk) {
+                Ok(len) => len,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error.into()),
+            };
+
+            frame.clear();
+            frame.extend_from_slice(format!("{len}\n").as_bytes());
+            frame.extend_from_slice(&chunk[..len]);
+
+            // A zero length chunk ends the body, the checksum goes out with it
+            if len == 0 {
+                frame.extend_from_slice(Checksum::from_hasher(hasher).as_bytes());
+                stream.write_all(&frame)?;
+                break;
+            }
+
+            stream.write_all(&frame)?;
+            hasher.update(&chunk[..len]);
+            written += len as u64;
+            if let Some(progress) = &self.progress {
+                progress.add(len as u64);
+            }
+        }
+
+        Ok(written)
+    }
+    /// Read the file body that was sent after self from the stream into the given writer, one chunk at a time. If writing to the
+    /// writer fails, or the body grows past the max file size, the rest of the body is still read (and thrown away) so the stream
+    /// stays usable, then the error is returned. Returns the Checksum the sender sent for the whole file, it is up to the caller
+    /// to compare it
+    /// 
+    /// If the transfer is cancelled through its Progress, or nothing more can be written, the sender is asked to stop. For a cancel
+    /// ShareError::Cancelled is returned once it has, it is also returned if the sender stopped on its own
+    pub fn read_body_from_stream<W: Write>(&mut self, stream: &mut Connection, writer: &mut W) -> Result<Checksum, ShareError> {
+        self.read_body(stream, writer, false)
+    }
+    /// Read the file body like read_body_from_stream(), if refused is true the sender is asked to stop right away
+    fn read_body<W: Write>(&mut self, stream: &mut Connection, writer: &mut W, refused: bool) -> Result<Checksum, ShareError> {
+        if !self.body_pending {
+            return Err(ShareError::Protocol(String::from("No file body was sent")));
+        }
+        self.body_pending = false;
+
+        let offset = self.command.offset.unwrap_or(0);
+        if let Some(progress) = &self.progress {
+            progress.start(offset, self.file_size);
+        }
+
+        let limits = ShareLimits { max_share_size: None, max_file_size: self.max_file_size };
+        let mut chunk = vec![0; CHUNK_SIZE];
+        // Bytes the file holds so far, the part before the offset is already there
+        let mut read = offset;
+        let mut write_error: Option<ShareError> = None;
+        let mut abort_sent = false;
+
+        loop {
+            let cancelled = self.progress.as_ref().is_some_and(|progress| progress.is_cancelled());
+            if cancelled && write_error.is_none() {
+                write_error = Some(ShareError::Cancelled);
+            }
+
+            // Ask the sender to stop once nothing it sends is kept, it will end the body with an abort line. The body has to be
+            // read until then so the stream stays usable
+            if (refused || write_error.is_some()) && !abort_sent {
+                stream.write_all(ABORT_HEADER)?;
+                stream.flush()?;
+                abort_sent = true;
+            }
+
+            // Read chunk header, formated like `chunk_length\n`
+            let len = match read_header(stream)? {
+                Header::Length(len) => len as usize,
+                // The sender stopped before the end of the body
+                Header::Abort => return Err(write_error.unwrap_or(ShareError::Cancelled)),
+            };
+
+            // Zero length chunk marks the end of the body
+            if len == 0 {
+                break;
+            }
+            if len > CHUNK_SIZE {
+                return Err(ShareError::Protocol(format!("Chunk of {len} bytes is larger than the max chunk size")));
+            }
+
+            stream.read_exact(&mut chunk[..len])?;
+            read += len as u64;
+
+            if write_error.is_none() {
+                // The sender may send more than the file_size it claimed, so the limit is checked on what was actually read
+                if let Err(error) = limits.check(ShareLimit::FileSize, read) {
+                    write_error = Some(error.into());
+                } else if let Err(error) = writer.write_all(&chunk[..len]) {
+                    write_error = Some(error.into());
+                } else if let Some(progress) = &self.progress {
+                    progress.add(len as u64);
+                }
+            }
+        }
+
+        let mut checksum = [0; CHECKSUM_LEN];
+        stream.read_exact(&mut checksum)?;
+
+        match write_error {
+            Some(error) => Err(error),
+            None => Ok(Checksum::from_bytes(checksum)),
+        }
+    }
+    /// Throw away the file body that was sent after self (if it has not been read yet) so the next Share can be read from the stream.
+    /// The sender is asked to stop first, so a large body is not sent for nothing
+    pub fn discard_body(&mut self, stream: &mut Connection) -> Result<(), ShareError> {
+        if self.body_pending {
+            // Nothing is stored so the body is not held to the max file size
+            self.max_file_size = None;
+
+            match self.read_body(stream, &mut io::sink(), true) {
+                // The sender stopped like it was asked to
+                Ok(_) | Err(ShareError::Cancelled) => (),
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(())
+    }
     /// Some commands may require this method to work properly, take the Upload command as an example, the Upload command is useless if
     /// there is no file loaded into self.file. Calling this method will prepare any data (like a file) into self. This method may also
     /// be used to handle commands before anything is sent
     /// 
     /// # Panics
     /// The only case this method will panic is if the command passed was None
-    pub fn prepare_data(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn prepare_data(&mut self) -> Result<(), ShareError> {
         match self.command.command_type() {
             Some(command) => {
                 match command {
@@ -257,20 +700,33 @@ impl Share {
                     // things.
                     CommandType::Help => {
                         println!(
-                            "{}\n{}\n{}\n{}\n{}",
+                            "{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
                             "----- Help Guide -----",
                             "EXIT - Exit the client",
-                            "UPLOAD [file] - Upload a file to the server",
-                            "RECEIVE [file] - Receive a file from the server",
-                            "CATALOG - Receive a list of files from the server",
+                            "UPLOAD [file] [--conflict fail|overwrite|keep-both|overwrite-if-newer] - Upload a file or directory to the \
+                            server, the conflict policy says what happens when the file is already there",
+                            "RECEIVE [file] - Receive a file or directory from the server",
+                            "CATALOG [directory] [--filter pattern] [--sort name|kind|size|modified] [--desc] [--offset n] [--limit n] - \
+                            Receive a page of the files in a directory on the server",
+                            "SEARCH [directory] [--name pattern] [--ext extension] [--min-size bytes] [--max-size bytes] [--after date] \
+                            [--before date] [--offset n] [--limit n] - Find files in a directory on the server and everything below it",
+                            "STAT [path] - Receive the size, time and permissions of a file or directory on the server, along with the \
+                            checksum of a file you may read",
+                            "PARTIAL [file] - Receive how much of an interrupted upload the server kept",
+                            "DELETE [file] - Delete a file from the server",
+                            "RENAME [source] [destination] - Rename or move a file on the server (MOVE does the same)",
+                            "COPY [source] [destination] - Copy a file on the server",
+                            "MKDIR [directory] - Create a directory on the server",
+                            "RMDIR [directory] - Remove an empty directory from the server",
+                            "PING - Check that the server is still there, this also keeps the connection from timing out",
+                            "Put arguments with spaces in quotes, like \"my file.txt\"",
                         );
                     }
-                    // Load file into vector
+                    // Open the file so it can be streamed after the Share
                     CommandType::Upload if self.current_location == Location::Client => {
-                        let mut file = File::open(self.command.arg.as_ref().unwrap())?;
-                    
-                        self.file = Some(Vec::new());
-                        file.read_to_end(&mut self.file.as_mut().unwrap())?;
+                        let path = self.command.local_path().unwrap().to_path_buf();
+
+                        self.load_file(&path)?;
                     },  
 
                     _ => eprintln!("Nothing to prepare"),
@@ -285,54 +741,214 @@ impl Share {
 
         Ok(())
     }
-    /// Execute the command
+    /// Execute the command, the stream is needed so file bodies can be read straight from it into storage
     /// # Panics
     /// The only case this method will panic is if the command passed was None
-    pub fn execute(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn execute(&mut self, stream: &mut Connection) -> Result<(), ShareError> {
         // If we are executing on the client side print the server response
         if self.current_location == Location::Client {
             println!("Server says: {:?}. STATUS: {:?}", self.server_response.text, self.server_response.status);
         }
         // If the server reports an error dont execute the command
-        if self.server_response.status == ServerResponseStatus::Error {
+        if self.server_response.is_error() {
             return Ok(());
         }
 
+        // Everything below relies on the command having the arguments its type needs
+        if self.current_location == Location::Server {
+            self.command.validate()?;
+        }
+
+        // Refuse commands the user is not allowed to run before the storage is touched
+        for (permission, path) in self.required_permissions()? {
+            self.check_access(path, permission)?;
+        }
+
+        // Refuse a file body that is over the limit before anything is written
+        if let Some(file_size) = self.file_size.filter(|_| self.body_pending) {
+            ShareLimits { max_share_size: None, max_file_size: self.max_file_size }
+                .check(ShareLimit::FileSize, file_size)?;
+        }
+
         // Check which command is trying to be executed
         match self.command.command_type() {
             Some(command) => {
                 match command {
-                    // Received a file from the server; Move file inside memory to storage
+                    // Received a file from the server; Stream the body into storage. A directory has no body, it is created here
+                    // and the files in it are received one at a time afterwards
                     CommandType::Receive if self.current_location == Location::Client => {
-                        let mut file = File::create(self.command.arg.as_ref().unwrap())?;
-                    
-                        file.write_all(&self.file.as_ref().unwrap())?;
+                        let path = self.command.local_path().unwrap().to_path_buf();
+
+                        if self.body_pending {
+                            self.receive_file(stream, &path)?;
+                        } else {
+                            fs::create_dir_all(&path)?;
+                        }
                     }
-                    // Send a file to the client; Move file inside storage to memory
+                    // Send a file to the client; Open the file so it is streamed after the Share. For a directory everything in it
+                    // is listed instead
                     CommandType::Receive if self.current_location == Location::Server => {
-                        let mut file = File::open(self.command.arg.as_ref().unwrap())?;
-                    
-                        self.file = Some(Vec::new());
-                        file.read_to_end(&mut self.file.as_mut().unwrap())?;
+                        let name = self.command.arg().unwrap();
+                        let path = self.storage.resolve(name)?;
+
+                        if path.is_dir() {
+                            let mut listing = String::new();
+                            self.list_tree(&path, name, "", &mut listing)?;
+                            self.text_data = Some(listing);
+                        } else {
+                            self.load_file(&path)?;
+                        }
                     }
-                    // Send a file to the client; Move file inside storage to memory
+                    // Received a file from the client; Stream the body into storage
                     CommandType::Upload if self.current_location == Location::Server => {
-                        let mut file = File::create(self.command.arg.as_ref().unwrap())?;
-                    
-                        file.write_all(&self.file.as_ref().unwrap())?;
+                        let name = self.command.arg().unwrap().to_string();
+                        let path = self.storage.resolve_file(&name)?;
+                        // The partial file is always next to the path the client asked for, so a resumed upload finds it no matter
+                        // where the finished one ends up
+                        let _claim = self.storage.claim_upload(&path, &name)?;
+
+                        // Refuse right away if the upload could not be stored anyway, a new name is only picked once it arrived
+                        if self.command.conflict.unwrap_or(self.storage.conflict_policy()) != ConflictPolicy::KeepBoth {
+                            self.upload_destination(&name, &path)?;
+                        }
+
+                        let checksum = self.receive_partial(stream, &path)?;
+
+                        // The file may have shown up while the body was sent, or since an earlier attempt of a resumed upload
+                        let (destination, _destination_claim) = match self.upload_destination(&name, &path) {
+                            Ok(destination) => destination,
+                            Err(error) => {
+                                // The upload is refused, there is nothing to resume
+                                let _ = fs::remove_file(partial_path(&path));
+                                return Err(error);
+                            }
+                        };
+                        self.commit_partial(&path, &destination)?;
+                        self.checksum = Some(checksum);
+
+                        // Keep the time of the original so OverwriteIfNewer compares the files and not when they were uploaded
+                        if let Some(modified) = self.file_modified {
+                            // The file is already stored, not being able to set its time is not worth failing the upload for
+                            let _ = File::options().write(true).open(&destination)
+                                .and_then(|file| file.set_modified(UNIX_EPOCH + Duration::from_secs(modified)));
+                        }
                     }
-                    // Load text_data with a list of files the server has
+                    // Load the catalog with the files the server has in the given directory
                     CommandType::Catalog if self.current_location == Location::Server => {
-                        let paths = fs::read_dir(".")?;
-                        self.text_data = Some(String::new());
+                        let dir = self.command.arg().unwrap_or(".");
+                        let path = self.storage.resolve(dir)?;
+                        if !path.is_dir() {
+                            return Err(ShareError::Path(format!("{dir} is not a directory")));
+                        }
+
+                        let query = self.command.query().cloned().unwrap_or_default();
+                        let filter = query.filter.as_deref().map(NameFilter::new).transpose()?;
+                        let mut entries = Vec::new();
                     
-                        for path in paths {
-                            self.text_data.as_mut()
-                                .unwrap()
-                                .push_str(
-                                    format!("{}\n", path?.path().display()).as_str().clone()
-                                );
+                        // Only the names are sent so the location of the storage root is not given away
+                        for (name, _) in list_dir(&path)? {
+                            if filter.as_ref().is_some_and(|filter| !filter.matches(&name)) {
+                                continue;
+                            }
+
+                            match CatalogEntry::read(&path.join(&name), name) {
+                                Ok(entry) => entries.push(entry),
+                                // Removed while the directory was being listed
+                                Err(ShareError::Io { kind: IoErrorKind::NotFound, .. }) => (),
+                                Err(error) => return Err(error),
+                            }
                         }
+
+                        // Every entry has to be read to sort them, but only the ones on the page are checked and hashed
+                        let mut page = query.page(entries, self.storage.catalog_page_size());
+                        self.describe_entries(&mut page, &path, dir)?;
+
+                        self.catalog = Some(page);
+                    }
+                    // Walk the directory and everything below it, the user only finds files in directories they may list
+                    CommandType::Search if self.current_location == Location::Server => {
+                        let dir = self.command.arg().unwrap_or(".");
+                        let path = self.storage.resolve(dir)?;
+                        if !path.is_dir() {
+                            return Err(ShareError::Path(format!("{dir} is not a directory")));
+                        }
+
+                        let query = self.command.search().cloned().unwrap_or_default();
+                        let filter = SearchFilter::new(&query)?;
+                        let mut matches = Vec::new();
+                        self.search_dir(&path, dir, "", &filter, &mut matches)?;
+
+                        let mut page = query.page(matches, self.storage.catalog_page_size());
+                        self.describe_entries(&mut page, &path, dir)?;
+
+                        self.catalog = Some(page);
+                    }
+                    // Read the metadata of a single path, files the user may read are always hashed since that is what STAT is
+                    // mostly used for
+                    CommandType::Stat if self.current_location == Location::Server => {
+                        let name = self.command.arg().unwrap();
+                        let path = self.storage.resolve(name)?;
+
+                        let mut entry = CatalogEntry::read(&path, name.to_string())?;
+                        entry.permissions = self.permissions_on(name);
+                        // The checksum tells what is in the file, which needs more than seeing that it is there
+                        if entry.kind == EntryKind::File && entry.permissions.contains(&Permission::Read) {
+                            entry.checksum = Some(Checksum::of_file(&path)?);
+                        }
+
+                        self.stat = Some(entry);
+                    }
+                    // Create a directory in storage, it is fine if it is already there
+                    CommandType::Mkdir if self.current_location == Location::Server => {
+                        let path = self.storage.resolve(self.command.arg().unwrap())?;
+
+                        fs::create_dir_all(&path)?;
+                    }
+                    // Remove a directory from storage, only empty directories are removed so nothing is lost by accident
+                    CommandType::Rmdir if self.current_location == Location::Server => {
+                        let dir = self.command.arg().unwrap();
+                        let path = self.storage.resolve(dir)?;
+
+                        if path == self.storage.root() {
+                            return Err(ShareError::Path(String::from("The storage root can not be removed")));
+                        }
+                        if !fs::symlink_metadata(&path)?.is_dir() {
+                            return Err(ShareError::Path(format!("{dir} is not a directory")));
+                        }
+
+                        fs::remove_dir(&path)?;
+                    }
+                    // Remove a file from storage, directories are never removed this way
+                    CommandType::Delete if self.current_location == Location::Server => {
+                        let path = self.storage.resolve(self.command.arg().unwrap())?;
+
+                        if fs::symlink_metadata(&path)?.is_dir() {
+                            return Err(ShareError::Path(format!("{} is a directory", self.command.arg().unwrap())));
+                        }
+
+                        fs::remove_file(&path)?;
+                    }
+                    // Move a file or directory inside storage, an existing destination is never replaced
+                    CommandType::Rename if self.current_location == Location::Server => {
+                        let (source, destination) = self.source_and_destination()?;
+
+                        fs::rename(source, destination)?;
+                    }
+                    // Copy a file inside storage, an existing destination is never replaced
+                    CommandType::Copy if self.current_location == Location::Server => {
+                        let (source, destination) = self.source_and_destination()?;
+
+                        if fs::metadata(&source)?.is_dir() {
+                            return Err(ShareError::Path(format!("{} is a directory", self.command.arg().unwrap())));
+                        }
+
+                        fs::copy(source, destination)?;
+                    }
+                    // Let the client know where to resume an interrupted upload, zero if nothing was kept
+                    CommandType::Partial if self.current_location == Location::Server => {
+                        let path = self.storage.resolve_file(self.command.arg().unwrap())?;
+
+                        self.command.offset = Some(partial_size_at(&partial_path(&path))?);
                     }
                 
                     _ => (),
@@ -346,11 +962,384 @@ impl Share {
 
         Ok(())
     }
+    /// Returns the permissions the command needs on the server and the paths it needs them for, empty if it does not use the
+    /// storage. Returns an error if the command does not have the arguments its type needs, so a malformed command never runs
+    /// without being checked
+    fn required_permissions(&self) -> Result<Vec<(Permission, &str)>, ShareError> {
+        let args = self.command.args();
+
+        let required: Vec<(Permission, &str)> = match (self.command.command_type(), args) {
+            (Some(CommandType::Receive), [path]) => vec![(Permission::Read, path)],
+            (Some(CommandType::Upload | CommandType::Partial | CommandType::Delete), [path]) => vec![(Permission::Write, path)],
+            (Some(CommandType::Mkdir | CommandType::Rmdir), [path]) => vec![(Permission::Write, path)],
+            (Some(CommandType::Catalog | CommandType::Search), [path]) => vec![(Permission::List, path)],
+            (Some(CommandType::Catalog | CommandType::Search), []) => vec![(Permission::List, ".")],
+            // The checksum is only sent if the user may read the file as well, the rest is what a listing would show
+            (Some(CommandType::Stat), [path]) => vec![(Permission::List, path)],
+            // The source is gone afterwards, so it needs the same permission as deleting it
+            (Some(CommandType::Rename), [source, destination]) => {
+                vec![(Permission::Write, source), (Permission::Write, destination)]
+            }
+            (Some(CommandType::Copy), [source, destination]) => {
+                vec![(Permission::Read, source), (Permission::Write, destination)]
+            }
+            // These never touch the storage
+            (Some(CommandType::Exit | CommandType::Help | CommandType::Login | CommandType::Ping), []) => Vec::new(),
+            (Some(command_type), _) => {
+                return Err(ShareError::Protocol(format!("{command_type:?} can not take {} arguments", args.len())));
+            }
+            (None, _) => return Err(ShareError::Protocol(String::from("No command type was sent"))),
+        };
+
+        Ok(required)
+    }
+    /// Add every file in the directory and its subdirectories that matches the filter. dir is the directory the search started in
+    /// as the client sent it, prefix is the path from there to this directory. Symlinks are never followed
+    fn search_dir(
+        &self,
+        path: &Path,
+        dir: &str,
+        prefix: &str,
+        filter: &SearchFilter,
+        matches: &mut Vec<CatalogEntry>,
+    ) -> Result<(), ShareError> {
+        for (name, is_dir) in list_dir(path)? {
+            let relative = format!("{prefix}{name}");
+
+            if is_dir {
+                // Directories the user may not list are skipped instead of failing the whole search
+                if self.permissions_on(&format!("{dir}{DIRECTORY_SUFFIX}{relative}")).contains(&Permission::List) {
+                    self.search_dir(&path.join(&name), dir, &format!("{relative}{DIRECTORY_SUFFIX}"), filter, matches)?;
+                }
+                continue;
+            }
+
+            match CatalogEntry::read(&path.join(&name), relative) {
+                Ok(entry) if filter.matches(&entry) => matches.push(entry),
+                Ok(_) => (),
+                // Removed while the directory was being searched
+                Err(ShareError::Io { kind: IoErrorKind::NotFound, .. }) => (),
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(())
+    }
+    /// Add a line to the listing for everything inside the directory and its subdirectories, prefix is the path of the directory
+    /// relative to where the listing started. Files the user may not read and directories they may not list are left out, they
+    /// would only fail when received
+    fn list_tree(&self, path: &Path, dir: &str, prefix: &str, listing: &mut String) -> Result<(), ShareError> {
+        for (name, is_dir) in list_dir(path)? {
+            let relative = format!("{prefix}{name}");
+            let permissions = self.permissions_on(&format!("{dir}{DIRECTORY_SUFFIX}{relative}"));
+
+            if is_dir && permissions.contains(&Permission::List) {
+                listing.push_str(&format!("{relative}{DIRECTORY_SUFFIX}\n"));
+                self.list_tree(&path.join(&name), dir, &format!("{relative}{DIRECTORY_SUFFIX}"), listing)?;
+            } else if !is_dir && permissions.contains(&Permission::Read) {
+                listing.push_str(&format!("{relative}\n"));
+            }
+        }
+
+        Ok(())
+    }
+    /// Fill in what the user may do with each entry of a page listed from the directory at path (dir as the client sent it), and
+    /// hash the files they may read if the server is set up to. Entries removed since they were listed are left out
+    fn describe_entries(&self, page: &mut CatalogPage, path: &Path, dir: &str) -> Result<(), ShareError> {
+        for mut entry in mem::take(&mut page.entries) {
+            entry.permissions = self.permissions_on(&format!("{dir}{DIRECTORY_SUFFIX}{}", entry.name));
+
+            // The checksum tells what is in the file, which needs more than seeing that it is there
+            if self.storage.catalog_checksums() && entry.kind == EntryKind::File && entry.allows(Permission::Read) {
+                match Checksum::of_file(&path.join(&entry.name)) {
+                    Ok(checksum) => entry.checksum = Some(checksum),
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                    Err(error) => return Err(error.into()),
+                }
+            }
+
+            page.entries.push(entry);
+        }
+
+        Ok(())
+    }
+    /// Returns the ACL the command is checked against, None if there is nothing to check
+    fn rules(&self) -> Option<&Acl> {
+        self.acl.as_deref().filter(|acl| !acl.is_empty())
+    }
+    /// Returns ShareError::PermissionDenied if the user that sent the command may not use the given path the given way. Symlinks
+    /// inside the storage are followed, the user needs the permission on the path they sent and on the path it leads to
+    fn check_access(&self, path: &str, permission: Permission) -> Result<(), ShareError> {
+        let acl = match self.rules() {
+            Some(acl) => acl,
+            None => return Ok(()),
+        };
+        let target = self.storage.resolve_target(path)?;
+
+        acl.check(self.user.as_deref(), path, permission)?;
+        acl.check(self.user.as_deref(), &target.to_string_lossy(), permission)
+    }
+    /// Returns every permission the user that sent the command has on the given path, see check_access()
+    fn permissions_on(&self, path: &str) -> Vec<Permission> {
+        let all = [Permission::Read, Permission::Write, Permission::List];
+        let acl = match self.rules() {
+            Some(acl) => acl,
+            None => return all.to_vec(),
+        };
+        let target = match self.storage.resolve_target(path) {
+            Ok(target) => target.to_string_lossy().to_string(),
+            Err(_) => return Vec::new(),
+        };
+
+        all.into_iter()
+            .filter(|permission| {
+                acl.check(self.user.as_deref(), path, *permission).is_ok() &&
+                    acl.check(self.user.as_deref(), &target, *permission).is_ok()
+            })
+            .collect()
+    }
+    /// Returns the first numbered name next to the given path that is free, along with it resolved and claimed for the upload.
+    /// The user needs to be allowed to write it too, rules can cover a single file
+    fn free_upload_path(&self, name: &str) -> Result<(String, PathBuf, UploadClaim), ShareError> {
+        for number in 1.. {
+            let free_name = numbered_path(name, number);
+            let path = self.storage.resolve_file(&free_name)?;
+
+            if fs::symlink_metadata(&path).is_ok() || fs::symlink_metadata(partial_path(&path)).is_ok() {
+                continue;
+            }
+            // Another upload picked the same name first
+            let claim = match self.storage.claim_upload(&path, &free_name) {
+                Ok(claim) => claim,
+                Err(_) => continue,
+            };
+
+            self.check_access(&free_name, Permission::Write)?;
+
+            return Ok((free_name, path, claim));
+        }
+
+        unreachable!("ran out of numbers for {name}")
+    }
+    /// Returns the source and destination of a RENAME or COPY resolved inside the storage. Returns an error if the source does not
+    /// exist or the destination does
+    fn source_and_destination(&self) -> Result<(PathBuf, PathBuf), ShareError> {
+        let (source, destination) = match self.command.args() {
+            [source, destination] => (source, destination),
+            _ => return Err(ShareError::Protocol(String::from("Expected a source and a destination"))),
+        };
+
+        let source_path = self.storage.resolve(source)?;
+        let destination_path = self.storage.resolve(destination)?;
+
+        if source_path == self.storage.root() {
+            return Err(ShareError::Path(String::from("The storage root can not be moved or copied")));
+        }
+        if fs::symlink_metadata(&source_path).is_err() {
+            return Err(ShareError::Path(format!("{source} does not exist")));
+        }
+        if fs::symlink_metadata(&destination_path).is_ok() {
+            return Err(ShareError::Path(format!("{destination} already exists")));
+        }
+
+        Ok((source_path, destination_path))
+    }
+    /// Open the file at the given path so it is streamed after the Share, starting at the command offset
+    fn load_file(&mut self, path: &Path) -> Result<(), ShareError> {
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let file_size = metadata.len();
+
+        let offset = self.command.offset.unwrap_or(0);
+        if offset > file_size {
+            return Err(ShareError::Protocol(format!("Offset {offset} is past the end of the {file_size} byte file")));
+        }
+
+        self.file_size = Some(file_size);
+        self.file_modified = metadata.modified().ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| modified.as_secs());
+        self.file = Some(file);
+
+        Ok(())
+    }
+    /// Stream the file body into the partial file next to the given path, it is synced to disk and renamed to the path once the
+    /// whole body arrived and its checksum matches. The file that was at the path before is only replaced by that rename, so it
+    /// stays untouched if anything goes wrong. With an offset the body is appended to what an earlier attempt left behind
+    fn receive_file(&mut self, stream: &mut Connection, path: &Path) -> Result<(), ShareError> {
+        let checksum = self.receive_partial(stream, path)?;
+        self.commit_partial(path, path)?;
+        self.checksum = Some(checksum);
+
+        Ok(())
+    }
+    /// Stream the file body into the partial file next to the given path and sync it to disk, returns its checksum once the whole
+    /// body arrived and it matches. With an offset the body is appended to what an earlier attempt left behind
+    /// 
+    /// If the body does not arrive because the connection failed the partial file is kept so the transfer can be resumed, for
+    /// anything else (it was cancelled, the body was too large, etc.) it is removed. If the checksum does not match a
+    /// ShareError::Checksum is returned and the file is moved to the quarantine of the storage, or removed if there is none
+    fn receive_partial(&mut self, stream: &mut Connection, path: &Path) -> Result<Checksum, ShareError> {
+        let part_path = partial_path(path);
+        let offset = self.command.offset.unwrap_or(0);
+
+        let mut writer = if offset > 0 {
+            let mut writer = HashingWriter::new(OpenOptions::new().read(true).append(true).open(&part_path)?);
+
+            // The checksum covers the whole file, so the part that is already there is hashed too
+            let existing = writer.hash_existing()?;
+            if existing != offset {
+                return Err(ShareError::Protocol(format!("Can not resume at {offset}, {existing} bytes were kept")));
+            }
+
+            writer
+        } else {
+            HashingWriter::new(File::create(&part_path)?)
+        };
+
+        let result = self.read_body_from_stream(stream, &mut writer).and_then(|expected| {
+            // The rename must never point the path at a file that is not fully written yet
+            writer.sync_all()?;
+            let actual = writer.finish();
+
+            if actual == expected {
+                Ok(actual)
+            } else {
+                Err(ShareError::Checksum { expected, actual })
+            }
+        });
+
+        match result {
+            Ok(checksum) => Ok(checksum),
+            // Never save a file that does not match what was sent
+            Err(error @ ShareError::Checksum { .. }) => {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+                match self.storage.quarantine_file(&part_path, &name) {
+                    Ok(Some(quarantined)) => eprintln!("{error}: moved {name} to {}", quarantined.display()),
+                    Ok(None) => (),
+                    // The file may already be gone, the error that caused this matters more
+                    Err(_) => { let _ = fs::remove_file(&part_path); }
+                }
+
+                Err(error)
+            }
+            // Keep what arrived so the transfer can be resumed
+            Err(error @ ShareError::Io { .. }) => Err(error),
+            Err(error) => {
+                // The file may already be gone, the error that caused this matters more
+                let _ = fs::remove_file(&part_path);
+
+                Err(error)
+            }
+        }
+    }
+    /// Rename the partial file received next to path to destination, the two are only different if the upload is kept next to a
+    /// file that was already there
+    fn commit_partial(&self, path: &Path, destination: &Path) -> Result<(), ShareError> {
+        fs::rename(partial_path(path), destination)?;
+        sync_parent(destination);
+
+        Ok(())
+    }
+    /// Returns where an upload to the given path is stored following its conflict policy, along with a claim on it if that is not
+    /// the path itself. Returns ShareError::Conflict if the upload may not be stored. name is the path as the client sent it
+    fn upload_destination(&mut self, name: &str, path: &Path) -> Result<(PathBuf, Option<UploadClaim>), ShareError> {
+        if fs::symlink_metadata(path).is_err() {
+            return Ok((path.to_path_buf(), None));
+        }
+
+        match self.command.conflict.unwrap_or(self.storage.conflict_policy()) {
+            ConflictPolicy::Overwrite => Ok((path.to_path_buf(), None)),
+            ConflictPolicy::Fail => Err(ShareError::Conflict(format!("{name} already exists"))),
+            ConflictPolicy::OverwriteIfNewer => {
+                let existing = fs::metadata(path)?.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+                if self.file_modified.is_none_or(|modified| modified <= existing.as_secs()) {
+                    return Err(ShareError::Conflict(format!("{name} on the server is as new or newer")));
+                }
+
+                Ok((path.to_path_buf(), None))
+            }
+            // The reply carries the name the file was stored under
+            ConflictPolicy::KeepBoth => {
+                let (free_name, free_path, claim) = self.free_upload_path(name)?;
+                self.command.args[0] = free_name;
+
+                Ok((free_path, Some(claim)))
+            }
+        }
+    }
+    /// Returns the command in execution
+    pub fn command(&self) -> &ShareCommand {
+        &self.command
+    }
+    /// Set the credentials sent with LOGIN
+    pub fn set_credentials(&mut self, credentials: Credentials) {
+        self.credentials = Some(credentials);
+    }
+    /// Take the credentials sent with LOGIN out of self, so they are not sent back
+    pub fn take_credentials(&mut self) -> Option<Credentials> {
+        self.credentials.take()
+    }
+    /// Returns the page of the directory the server listed in reply to CATALOG
+    pub fn catalog(&self) -> Option<&CatalogPage> {
+        self.catalog.as_ref()
+    }
+    /// Returns the metadata of the path the server replied to a STAT with
+    pub fn stat(&self) -> Option<&CatalogEntry> {
+        self.stat.as_ref()
+    }
+    /// Returns the paths (relative to the directory, separated with `/`) of everything in a directory the server replied to a
+    /// RECEIVE with, directories end with DIRECTORY_SUFFIX and come before what is in them. None if a file was received
+    pub fn directory_listing(&self) -> Option<Vec<&str>> {
+        if self.command.command_type() != Some(&CommandType::Receive) || self.file_size.is_some() {
+            return None;
+        }
+
+        Some(self.text_data.as_deref()?.lines().filter(|line| !line.is_empty()).collect())
+    }
+    /// Returns the checksum of the file that was received, for an UPLOAD this is the checksum the server confirmed
+    pub fn checksum(&self) -> Option<&Checksum> {
+        self.checksum.as_ref()
+    }
+    /// Set the path of the file on the client. The local path is never sent, so it has to be set again on the Share the server
+    /// replies with before it is executed
+    pub fn set_local_path(&mut self, local_path: PathBuf) {
+        self.command.local_path = Some(local_path);
+    }
+    /// Set the Progress that is updated while the file body is sent or received, it can be used to cancel the transfer. The
+    /// Progress is never sent, so it has to be set again on the Share the server replies with before it is executed
+    pub fn set_progress(&mut self, progress: Arc<Progress>) {
+        self.progress = Some(progress);
+    }
+    /// Set the storage the command is executed in on the server, paths sent by the client are resolved inside of it
+    pub fn set_storage(&mut self, storage: Storage) {
+        self.storage = storage;
+    }
+    /// Set the ACL the command is checked against on the server, and the user that sent it (None if they did not log in)
+    pub fn set_access(&mut self, acl: Arc<Acl>, user: Option<String>) {
+        self.acl = Some(acl);
+        self.user = user;
+    }
     /// Set the server error response
-    pub fn set_error_response(&mut self, error: Box<dyn std::error::Error>) {
+    pub fn set_error_response(&mut self, error: ShareError) {
         self.server_response.status = ServerResponseStatus::Error;
         // Convert the error to a string
         self.server_response.text = Some(error.to_string());
+        // Send the error itself too so the client can match on it
+        self.server_response.error = Some(error);
+    }
+    /// Returns the error the server reported as a ShareError::Server, or Ok(()) if the server reported success
+    pub fn server_result(&self) -> Result<(), ShareError> {
+        if !self.server_response.is_error() {
+            return Ok(());
+        }
+
+        let error = self.server_response.error.clone().unwrap_or_else(|| {
+            ShareError::Protocol(self.server_response.text.clone().unwrap_or_default())
+        });
+
+        Err(ShareError::Server(Box::new(error)))
     }
 }
 
@@ -367,6 +1356,8 @@ struct ServerResponse {
     status: ServerResponseStatus,
 
     text: Option<String>,
+    /// Contains the error the server ran into when the status is Error
+    error: Option<ShareError>,
 }
 
 impl ServerResponse {
@@ -375,6 +1366,514 @@ impl ServerResponse {
         ServerResponse {
             status: ServerResponseStatus::Success,
             text: Some(String::from("OK")),
+            error: None,
         }
     }
-}
\ No newline at end of file
+    /// Returns true if the server reported an error
+    fn is_error(&self) -> bool {
+        self.status == ServerResponseStatus::Error
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+/// Contains which size limit was checked
+pub enum ShareLimit {
+    /// Size of a Share, not including its file body
+    ShareSize,
+    /// Size of a file body
+    FileSize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Contains the size limits used when reading a Share from a stream, None means there is no limit
+pub struct ShareLimits {
+    /// Max size (in bytes) of a Share, not including its file body
+    pub max_share_size: Option<u64>,
+    /// Max size (in bytes) of a file body
+    pub max_file_size: Option<u64>,
+}
+
+impl ShareLimits {
+    /// Returns a LimitError if size is over the max set for the given limit
+    fn check(&self, limit: ShareLimit, size: u64) -> Result<(), LimitError> {
+        let max = match limit {
+            ShareLimit::ShareSize => self.max_share_size,
+            ShareLimit::FileSize => self.max_file_size,
+        };
+
+        match max {
+            Some(max) if size > max => Err(LimitError { limit, max, size }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+/// Error returned when a Share or file body is larger than allowed
+pub struct LimitError {
+    /// The limit that was hit
+    pub limit: ShareLimit,
+    /// The max size (in bytes) allowed
+    pub max: u64,
+    /// The size (in bytes) that was sent
+    pub size: u64,
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let limit = match self.limit {
+            ShareLimit::ShareSize => "max share size",
+            ShareLimit::FileSize => "max file size",
+        };
+
+        write!(f, "Limit error: {} bytes is over the {limit} of {} bytes", self.size, self.max)
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+/// Split a command into words on whitespace. Text in single or double quotes is kept together (quotes can be used inside the
+/// other kind), so `COPY "a b.txt" c.txt` gives `COPY`, `a b.txt` and `c.txt`
+fn split_args(command: &str) -> Result<Vec<String>, ShareError> {
+    let mut args = Vec::new();
+    // Word being read, None between words so `""` still gives an empty word
+    let mut current: Option<String> = None;
+    let mut quote = None;
+
+    for char in command.chars() {
+        match (quote, char) {
+            (Some(open), char) if char == open => quote = None,
+            (Some(_), char) => current.get_or_insert_with(String::new).push(char),
+            (None, '"' | '\'') => {
+                quote = Some(char);
+                current.get_or_insert_with(String::new);
+            }
+            (None, char) if char.is_whitespace() => args.extend(current.take()),
+            (None, char) => current.get_or_insert_with(String::new).push(char),
+        }
+    }
+
+    if let Some(open) = quote {
+        return Err(ShareError::Parse(format!("Missing closing {open}")));
+    }
+    args.extend(current);
+
+    Ok(args)
+}
+
+/// Returns the name of every entry in a directory and true for the ones that are directories, sorted by name. Interrupted uploads
+/// are left out since they are not files anyone can receive yet. Symlinks are never followed
+fn list_dir(path: &Path) -> Result<Vec<(String, bool)>, ShareError> {
+    let mut entries = Vec::new();
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if is_partial(&path) {
+            continue;
+        }
+
+        entries.push((entry.file_name().to_string_lossy().to_string(), entry.file_type()?.is_dir()));
+    }
+    entries.sort();
+
+    Ok(entries)
+}
+
+/// Flush the directory the given path is in to disk, so a rename into it survives a crash. Directories can not be opened on every
+/// platform, the file itself is already synced so this is done on a best effort basis
+fn sync_parent(path: &Path) {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    if let Ok(dir) = File::open(parent) {
+        let _ = dir.sync_all();
+    }
+}
+
+/// Returns the path a file body is received into before it is complete, it sits next to the final path
+pub fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(PARTIAL_EXTENSION);
+
+    path.with_file_name(name)
+}
+
+/// Returns true if the path names a partial file, like the ones partial_path() returns
+pub fn is_partial(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == PARTIAL_EXTENSION)
+}
+
+/// Returns how much of the file at the given path an interrupted RECEIVE left behind, zero if nothing was kept. This is the
+/// offset to resume the RECEIVE at
+pub fn partial_size(path: &Path) -> Result<u64, ShareError> {
+    partial_size_at(&partial_path(path))
+}
+
+/// Returns the size of the partial file at the given path, zero if there is none
+fn partial_size_at(part_path: &Path) -> Result<u64, ShareError> {
+    match fs::metadata(part_path) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Contains a header line read from the stream
+enum Header {
+    /// A length formated like `length\n`
+    Length(u64),
+    /// An `abort\n` line
+    Abort,
+}
+
+/// Read a header formated like `length\n` (or `abort\n`) from the stream. This reads a single byte at a time so nothing after the
+/// newline is taken off the stream
+fn read_header(stream: &mut Connection) -> Result<Header, ShareError> {
+    let mut header = Vec::new();
+    let mut byte = [0; 1];
+
+    loop {
+        stream.read_exact(&mut byte)?;
+
+        if byte[0] == b'\n' {
+            break;
+        }
+        if header.len() == MAX_HEADER_LEN {
+            return Err(ShareError::Protocol(String::from("Header is too long")));
+        }
+
+        header.push(byte[0]);
+    }
+
+    let header = String::from_utf8_lossy(&header);
+    let header = header.trim();
+
+    if header.as_bytes() == &ABORT_HEADER[..ABORT_HEADER.len() - 1] {
+        return Ok(Header::Abort);
+    }
+
+    header.parse().map(Header::Length).map_err(|_| {
+        ShareError::Protocol(String::from("Header is not a valid length"))
+    })
+}
+
+/// Read a header that must be a length
+fn read_length(stream: &mut Connection) -> Result<u64, ShareError> {
+    match read_header(stream)? {
+        Header::Length(len) => Ok(len),
+        Header::Abort => Err(ShareError::Protocol(String::from("Expected a length but got an abort"))),
+    }
+}
+
+/// Returns true if the other side sent an `abort\n` line asking us to stop sending a file body. This never blocks, nothing else is
+/// ever sent to us while we send a body so anything waiting on the stream must be an abort
+fn abort_requested(stream: &mut Connection) -> Result<bool, ShareError> {
+    // Nothing is waiting, or the connection was closed (which the next write will report)
+    if !stream.data_waiting()? {
+        return Ok(false);
+    }
+
+    match read_header(stream)? {
+        Header::Abort => Ok(true),
+        Header::Length(_) => Err(ShareError::Protocol(String::from("Unexpected data while sending a file body"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::SystemTime};
+
+    use super::*;
+    use crate::test_util::{TestDir, connected, tcp_pair};
+
+    /// Read a single Share on the server end and reply to it, like the server does
+    fn serve(stream: &mut Connection, storage: Storage, limits: ShareLimits) {
+        let mut share = Share::read_from_stream_limited(stream, Location::Server, limits).unwrap();
+        share.set_storage(storage);
+
+        if let Err(error) = share.execute(stream) {
+            share.set_error_response(error);
+        }
+        share.discard_body(stream).unwrap();
+        share.write_to_stream(stream, Location::Server).unwrap();
+    }
+
+    /// Send a command from the client end and execute the reply, like the client does
+    fn send(stream: &mut Connection, command: ShareCommand, progress: &Arc<Progress>) -> Result<Share, ShareError> {
+        let mut share = Share::new(command, Location::Client);
+        share.set_progress(Arc::clone(progress));
+        share.prepare_data()?;
+
+        if let Err(error) = share.write_to_stream(stream, Location::Client) {
+            if error == ShareError::Cancelled {
+                Share::read_from_stream(stream, Location::Client)?.server_result()?;
+            }
+            return Err(error);
+        }
+
+        let mut reply = Share::read_from_stream(stream, Location::Client)?;
+        if let Some(local_path) = share.command().local_path() {
+            reply.set_local_path(local_path.to_path_buf());
+        }
+        reply.set_progress(Arc::clone(progress));
+        reply.server_result()?;
+        reply.execute(stream)?;
+
+        Ok(reply)
+    }
+
+    fn transfer(command_type: CommandType, name: &str, local_path: &str) -> ShareCommand {
+        ShareCommandBuilder::new()
+            .command_type(command_type)
+            .arg(name.to_string())
+            .local_path(PathBuf::from(local_path))
+            .build()
+    }
+
+    fn upload(name: &str, local_path: &str) -> ShareCommand {
+        transfer(CommandType::Upload, name, local_path)
+    }
+
+    fn command(command_type: CommandType, args: &[&str]) -> Share {
+        let mut builder = ShareCommandBuilder::new().command_type(command_type);
+        for arg in args {
+            builder = builder.arg(arg.to_string());
+        }
+
+        Share::new(builder.build(), Location::Server)
+    }
+
+    #[test]
+    fn split_args_on_whitespace() {
+        assert_eq!(split_args("COPY  a.txt\tb.txt ").unwrap(), ["COPY", "a.txt", "b.txt"]);
+        assert!(split_args("   ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn split_args_keeps_quoted_text_together() {
+        assert_eq!(split_args("COPY \"a b.txt\" 'c d.txt'").unwrap(), ["COPY", "a b.txt", "c d.txt"]);
+        assert_eq!(split_args("UPLOAD \"it's.txt\"").unwrap(), ["UPLOAD", "it's.txt"]);
+        assert_eq!(split_args("UPLOAD dir/\"a b\".txt").unwrap(), ["UPLOAD", "dir/a b.txt"]);
+        assert_eq!(split_args("STAT \"\"").unwrap(), ["STAT", ""]);
+    }
+
+    #[test]
+    fn split_args_refuses_open_quotes() {
+        assert!(matches!(split_args("UPLOAD \"a.txt"), Err(ShareError::Parse(_))));
+        assert!(matches!(split_args("UPLOAD 'a.txt"), Err(ShareError::Parse(_))));
+    }
+
+    #[test]
+    fn parse_checks_the_amount_of_arguments() {
+        assert!(ShareCommand::parse("UPLOAD").is_err());
+        assert!(ShareCommand::parse("UPLOAD a.txt b.txt").is_err());
+        assert!(ShareCommand::parse("RENAME a.txt").is_err());
+        assert!(ShareCommand::parse("PING now").is_err());
+        assert!(ShareCommand::parse("CATALOG a b").is_err());
+        assert!(ShareCommand::parse("CATALOG").is_ok());
+        assert!(ShareCommand::parse("RENAME a.txt b.txt").is_ok());
+    }
+
+    #[test]
+    fn required_permissions_cover_every_path() {
+        let share = command(CommandType::Copy, &["a.txt", "b.txt"]);
+        assert_eq!(share.required_permissions().unwrap(), [(Permission::Read, "a.txt"), (Permission::Write, "b.txt")]);
+
+        let share = command(CommandType::Rename, &["a.txt", "b.txt"]);
+        assert_eq!(share.required_permissions().unwrap(), [(Permission::Write, "a.txt"), (Permission::Write, "b.txt")]);
+
+        let share = command(CommandType::Catalog, &[]);
+        assert_eq!(share.required_permissions().unwrap(), [(Permission::List, ".")]);
+
+        let share = command(CommandType::Ping, &[]);
+        assert!(share.required_permissions().unwrap().is_empty());
+    }
+
+    #[test]
+    fn required_permissions_refuse_the_wrong_amount_of_arguments() {
+        let malformed = [
+            command(CommandType::Upload, &["a.txt", "b.txt"]),
+            command(CommandType::Delete, &[]),
+            command(CommandType::Delete, &["a.txt", "b.txt"]),
+            command(CommandType::Receive, &[]),
+            command(CommandType::Stat, &[]),
+            command(CommandType::Rename, &["a.txt"]),
+            command(CommandType::Copy, &["a.txt", "b.txt", "c.txt"]),
+            command(CommandType::Catalog, &["a", "b"]),
+            command(CommandType::Ping, &["a"]),
+        ];
+
+        for share in malformed {
+            assert!(share.required_permissions().is_err(), "{:?} was accepted", share.command());
+            assert!(share.command().validate().is_err(), "{:?} was accepted", share.command());
+        }
+    }
+
+    #[test]
+    fn validate_needs_a_command_type() {
+        let share = Share::new(ShareCommandBuilder::new().build(), Location::Server);
+
+        assert!(share.command().validate().is_err());
+        assert!(share.required_permissions().is_err());
+    }
+
+    #[test]
+    fn file_bodies_over_the_limit_are_refused_and_stopped() {
+        let dir = TestDir::new("limit");
+        let storage = Storage::build(&dir.path("root")).unwrap();
+        let size = 32 * 1024 * 1024;
+        fs::write(dir.path("big.bin"), vec![7; size]).unwrap();
+
+        let (mut client, mut server) = connected();
+        let limits = ShareLimits { max_share_size: None, max_file_size: Some(1024) };
+        let server = thread::spawn(move || serve(&mut server, storage, limits));
+
+        let progress = Progress::new();
+        let result = send(&mut client, upload("big.bin", &dir.path("big.bin")), &progress);
+        server.join().unwrap();
+
+        assert!(matches!(result, Err(ShareError::Server(error)) if matches!(*error, ShareError::Limit(_))));
+        // The server asked for the body to stop instead of reading all of it
+        assert!(progress.done() < size as u64);
+        assert!(!Path::new(&dir.path("root/big.bin")).exists());
+    }
+
+    #[test]
+    fn file_bodies_are_sent_in_chunks_both_ways() {
+        let dir = TestDir::new("chunks");
+        let storage = Storage::build(&dir.path("root")).unwrap();
+        let file: Vec<u8> = (0..CHUNK_SIZE * 3 + 100).map(|byte| byte as u8).collect();
+        fs::write(dir.path("sent.bin"), &file).unwrap();
+
+        let (mut client, mut server) = connected();
+        let limits = ShareLimits { max_share_size: None, max_file_size: None };
+        let server = thread::spawn(move || {
+            for _ in 0..2 {
+                serve(&mut server, storage.clone(), limits);
+            }
+        });
+
+        let progress = Progress::new();
+        send(&mut client, upload("file.bin", &dir.path("sent.bin")), &progress).unwrap();
+        assert_eq!(progress.done(), file.len() as u64);
+        assert_eq!(fs::read(dir.path("root/file.bin")).unwrap(), file);
+
+        let progress = Progress::new();
+        send(&mut client, transfer(CommandType::Receive, "file.bin", &dir.path("received.bin")), &progress).unwrap();
+        assert_eq!(progress.done(), file.len() as u64);
+        assert_eq!(fs::read(dir.path("received.bin")).unwrap(), file);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn cancelled_uploads_are_stopped_and_the_connection_is_kept() {
+        let dir = TestDir::new("abort");
+        let storage = Storage::build(&dir.path("root")).unwrap();
+        fs::write(dir.path("sent.bin"), vec![7; CHUNK_SIZE * 4]).unwrap();
+
+        let (mut client, mut server) = connected();
+        let limits = ShareLimits { max_share_size: None, max_file_size: None };
+        let server = thread::spawn(move || {
+            for _ in 0..2 {
+                serve(&mut server, storage.clone(), limits);
+            }
+        });
+
+        let progress = Progress::new();
+        progress.cancel();
+        let result = send(&mut client, upload("cancelled.bin", &dir.path("sent.bin")), &progress);
+        assert!(result.is_err_and(|error| error.is_cancelled()));
+        assert!(!Path::new(&dir.path("root/cancelled.bin")).exists());
+
+        // The abort ended the body, so the next command is read like any other
+        send(&mut client, upload("kept.bin", &dir.path("sent.bin")), &Progress::new()).unwrap();
+        assert!(Path::new(&dir.path("root/kept.bin")).exists());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn uploads_that_fail_their_checksum_are_quarantined() {
+        let dir = TestDir::new("quarantine");
+        let mut storage = Storage::build(&dir.path("root")).unwrap();
+        fs::create_dir(dir.path("quarantine")).unwrap();
+        storage.set_quarantine(&dir.path("quarantine")).unwrap();
+        fs::write(dir.path("sent.bin"), vec![7; CHUNK_SIZE + 100]).unwrap();
+
+        // Record the upload as it goes over the wire and change the checksum at the end of it
+        let (sender, mut recorder) = tcp_pair();
+        let mut share = Share::new(upload("file.bin", &dir.path("sent.bin")), Location::Client);
+        share.prepare_data().unwrap();
+        share.write_to_stream(&mut Connection::Plain(sender), Location::Client).unwrap();
+        let mut sent = Vec::new();
+        recorder.read_to_end(&mut sent).unwrap();
+        *sent.last_mut().unwrap() ^= 1;
+
+        let (mut client, mut server) = connected();
+        client.write_all(&sent).unwrap();
+        let mut share = Share::read_from_stream(&mut server, Location::Server).unwrap();
+        share.set_storage(storage);
+
+        assert!(matches!(share.execute(&mut server), Err(ShareError::Checksum { .. })));
+        assert!(!Path::new(&dir.path("root/file.bin")).exists());
+        assert!(!partial_path(Path::new(&dir.path("root/file.bin"))).exists());
+
+        let quarantined: Vec<_> = fs::read_dir(dir.path("quarantine")).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(quarantined.len(), 1);
+        assert!(quarantined[0].file_name().unwrap().to_string_lossy().starts_with("file.bin."));
+        assert_eq!(fs::read(&quarantined[0]).unwrap(), fs::read(dir.path("sent.bin")).unwrap());
+    }
+
+    #[test]
+    fn conflict_policies_decide_what_happens_to_existing_files() {
+        let dir = TestDir::new("conflict");
+        let storage = Storage::build(&dir.path("root")).unwrap();
+        fs::write(dir.path("root/file.txt"), "old").unwrap();
+        fs::write(dir.path("new.txt"), "new").unwrap();
+
+        let (mut client, mut server) = connected();
+        let limits = ShareLimits { max_share_size: None, max_file_size: None };
+        let server = thread::spawn(move || {
+            for _ in 0..5 {
+                serve(&mut server, storage.clone(), limits);
+            }
+        });
+
+        let mut upload = |policy: ConflictPolicy, modified: SystemTime| {
+            File::options().write(true).open(dir.path("new.txt")).unwrap().set_modified(modified).unwrap();
+            let command = ShareCommandBuilder::new()
+                .command_type(CommandType::Upload)
+                .arg(String::from("file.txt"))
+                .local_path(PathBuf::from(dir.path("new.txt")))
+                .conflict(policy)
+                .build();
+
+            send(&mut client, command, &Progress::new())
+        };
+        let stored = || fs::read_to_string(dir.path("root/file.txt")).unwrap();
+        let (older, newer) = (UNIX_EPOCH + Duration::from_secs(1000), SystemTime::now() + Duration::from_secs(1000));
+
+        assert!(upload(ConflictPolicy::Fail, newer).is_err_and(|error| error.is_conflict()));
+        assert_eq!(stored(), "old");
+
+        let reply = upload(ConflictPolicy::KeepBoth, newer).unwrap();
+        assert_eq!(reply.command().arg(), Some("file (1).txt"));
+        assert_eq!(fs::read_to_string(dir.path("root/file (1).txt")).unwrap(), "new");
+        assert_eq!(stored(), "old");
+
+        assert!(upload(ConflictPolicy::OverwriteIfNewer, older).is_err_and(|error| error.is_conflict()));
+        assert_eq!(stored(), "old");
+
+        upload(ConflictPolicy::OverwriteIfNewer, newer).unwrap();
+        assert_eq!(stored(), "new");
+
+        fs::write(dir.path("new.txt"), "newest").unwrap();
+        upload(ConflictPolicy::Overwrite, older).unwrap();
+        assert_eq!(stored(), "newest");
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,45 @@
+use std::vec;
+
+use crate::ShareError;
+
+/// Walks the `--option value` style options in the arguments of a command, the arguments that are not options are kept so they
+/// can be taken out afterwards with rest(). Used by every command that takes options
+pub(crate) struct CommandOptions {
+    args: vec::IntoIter<String>,
+    rest: Vec<String>,
+}
+
+impl CommandOptions {
+    pub(crate) fn new(args: Vec<String>) -> CommandOptions {
+        CommandOptions { args: args.into_iter(), rest: Vec::new() }
+    }
+    /// Returns the next option, None once every argument was looked at
+    pub(crate) fn next_option(&mut self) -> Option<String> {
+        for arg in self.args.by_ref() {
+            if arg.starts_with("--") {
+                return Some(arg);
+            }
+            self.rest.push(arg);
+        }
+
+        None
+    }
+    /// Returns the value that comes after the given option
+    pub(crate) fn value(&mut self, option: &str) -> Result<String, ShareError> {
+        self.args.next().ok_or_else(|| ShareError::Parse(format!("{option} needs a value")))
+    }
+    /// Returns the value that comes after the given option as a number
+    pub(crate) fn number(&mut self, option: &str) -> Result<u64, ShareError> {
+        let value = self.value(option)?;
+        value.parse().map_err(|_| ShareError::Parse(format!("{option} needs a number, got {value}")))
+    }
+    /// Returns the arguments that are not options
+    pub(crate) fn rest(self) -> Vec<String> {
+        self.rest
+    }
+}
+
+/// Returns the error for an option the command does not know
+pub(crate) fn unknown_option(option: &str) -> ShareError {
+    ShareError::Parse(format!("Unknown option {option}"))
+}
@@ -0,0 +1,55 @@
+use std::sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}};
+
+/// Used as the total when the size of the transfer is not known yet
+const UNKNOWN_TOTAL: u64 = u64::MAX;
+
+#[derive(Debug)]
+/// Contains the progress of a file body being sent or received. This is shared between the thread doing the transfer and whoever is
+/// watching it (like a UI), so every field can be read or changed from another thread while the transfer runs
+pub struct Progress {
+    /// Bytes sent or received so far
+    done: AtomicU64,
+    /// Size of the whole body
+    total: AtomicU64,
+    /// Set to stop the transfer, it stops after the chunk it is working on
+    cancelled: AtomicBool,
+}
+
+impl Progress {
+    /// Create a new Progress that can be given to a Share and watched from somewhere else
+    pub fn new() -> Arc<Progress> {
+        Arc::new(Progress {
+            done: AtomicU64::new(0),
+            total: AtomicU64::new(UNKNOWN_TOTAL),
+            cancelled: AtomicBool::new(false),
+        })
+    }
+    /// Returns the amount of bytes sent or received so far
+    pub fn done(&self) -> u64 {
+        self.done.load(Ordering::Relaxed)
+    }
+    /// Returns the size of the whole body, None until the transfer has started
+    pub fn total(&self) -> Option<u64> {
+        match self.total.load(Ordering::Relaxed) {
+            UNKNOWN_TOTAL => None,
+            total => Some(total),
+        }
+    }
+    /// Ask the transfer to stop, the other side is told so it can clean up
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+    /// Returns true if cancel() was called
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+    /// Start counting a new body of the given size, done is where it starts when a transfer is resumed
+    pub(crate) fn start(&self, done: u64, total: Option<u64>) {
+        self.done.store(done, Ordering::Relaxed);
+        self.total.store(total.unwrap_or(UNKNOWN_TOTAL), Ordering::Relaxed);
+    }
+    /// Count bytes that were sent or received
+    pub(crate) fn add(&self, bytes: u64) {
+        self.done.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,287 @@
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{read_length, Connection, ShareError};
+
+/// Name sent at the start of the handshake so other programs talking to the port are turned away
+const PROTOCOL_NAME: &str = "file_share";
+/// Version of the wire format, bump this whenever Share, ShareCommand, ServerResponse or ShareError change
+pub const PROTOCOL_VERSION: u32 = 16;
+/// Oldest version of the wire format this build can still talk
+pub const MIN_PROTOCOL_VERSION: u32 = 16;
+/// Max size (in bytes) of a handshake message, this is read before anything else so it is kept small
+const MAX_HELLO_SIZE: u64 = 4096;
+
+/// Optional features this build supports, these are agreed on during the handshake
+pub const SUPPORTED_CAPABILITIES: &[&str] = &[RESUME];
+
+/// Interrupted UPLOAD and RECEIVE commands can pick up where they stopped, and PARTIAL can be used
+pub const RESUME: &str = "resume";
+
+#[derive(Serialize, Deserialize, Debug)]
+/// First message sent by the client after connecting. Its layout must never change so any version can read it
+struct Hello {
+    protocol: String,
+    version: u32,
+    min_version: u32,
+    /// Capabilities are sent by name so a peer can skip ones it does not know
+    capabilities: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// Sent by the server in reply to a Hello. Its layout must never change so any version can read it
+struct HelloResponse {
+    accepted: bool,
+    /// Version both sides will use
+    version: u32,
+    /// Capabilities both sides support
+    capabilities: Vec<String>,
+    /// Why the client was rejected
+    reason: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+/// Contains what the client and server agreed on during the handshake
+pub struct Handshake {
+    version: u32,
+    capabilities: Vec<String>,
+}
+
+impl Handshake {
+    /// Run the client side of the handshake, this must be done right after connecting. disabled is a list of capabilities the client
+    /// does not want to use even if the server supports them. Returns an error containing the servers reason if it rejected us
+    pub fn client(stream: &mut Connection, disabled: &[String]) -> Result<Handshake, ShareError> {
+        let hello = Hello {
+            protocol: PROTOCOL_NAME.to_string(),
+            version: PROTOCOL_VERSION,
+            min_version: MIN_PROTOCOL_VERSION,
+            capabilities: enabled_capabilities(disabled),
+        };
+        write_message(stream, &hello)?;
+        stream.flush()?;
+
+        let response: HelloResponse = read_message(stream)?;
+
+        if !response.accepted {
+            return Err(
+                ShareError::Protocol(format!(
+                    "Server rejected connection: {}",
+                    response.reason.unwrap_or_else(|| String::from("No reason given")),
+                ))
+            );
+        }
+
+        // Only keep capabilities we asked for, in case the server sent something else
+        let capabilities = response.capabilities.into_iter()
+            .filter(|capability| hello.capabilities.contains(capability))
+            .collect();
+
+        Ok(Handshake { version: response.version, capabilities })
+    }
+    /// Run the server side of the handshake, this must be done right after accepting a connection. disabled is a list of capabilities
+    /// the server does not want to use even if the client supports them. If the client can not be served it is sent a readable reason
+    /// and an error is returned, the connection should then be closed
+    pub fn server(stream: &mut Connection, disabled: &[String]) -> Result<Handshake, ShareError> {
+        let hello: Hello = match read_message(stream) {
+            Ok(hello) => hello,
+            // Most likely a client from before the handshake existed, or some other program
+            Err(error) => {
+                // The peer may already be gone so a failed write is ignored
+                let _ = reject(stream, "Could not read handshake, the client may be too old");
+                return Err(ShareError::Protocol(format!("Could not read handshake: {error}")));
+            }
+        };
+
+        // Pick the newest version both sides can talk
+        let version = hello.version.min(PROTOCOL_VERSION);
+
+        let rejection = if hello.protocol != PROTOCOL_NAME {
+            Some(format!("Unknown protocol: {}", hello.protocol))
+        } else if version < hello.min_version || version < MIN_PROTOCOL_VERSION {
+            Some(
+                format!(
+                    "Client supports protocol versions {} to {}, server supports versions {} to {}. Update the {}",
+                    hello.min_version, hello.version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+                    if hello.version < MIN_PROTOCOL_VERSION { "client" } else { "server" },
+                )
+            )
+        } else {
+            None
+        };
+
+        if let Some(reason) = rejection {
+            reject(stream, &reason)?;
+
+            return Err(ShareError::Protocol(reason));
+        }
+
+        // Use the capabilities both sides support
+        let capabilities: Vec<String> = enabled_capabilities(disabled).into_iter()
+            .filter(|capability| hello.capabilities.contains(capability))
+            .collect();
+
+        write_message(stream, &HelloResponse {
+            accepted: true,
+            version,
+            capabilities: capabilities.clone(),
+            reason: None,
+        })?;
+        stream.flush()?;
+
+        Ok(Handshake { version, capabilities })
+    }
+    /// Returns the protocol version that was agreed on
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+    /// Returns the capabilities both sides agreed to use
+    pub fn capabilities(&self) -> &[String] {
+        &self.capabilities
+    }
+    /// Returns true if both sides agreed to use the given capability
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|agreed| agreed == capability)
+    }
+}
+
+/// Send a HelloResponse rejecting the client with the given reason
+fn reject(stream: &mut Connection, reason: &str) -> Result<(), ShareError> {
+    write_message(stream, &HelloResponse {
+        accepted: false,
+        version: PROTOCOL_VERSION,
+        capabilities: Vec::new(),
+        reason: Some(reason.to_string()),
+    })?;
+    stream.flush()?;
+
+    Ok(())
+}
+
+/// Returns the supported capabilities that were not disabled
+fn enabled_capabilities(disabled: &[String]) -> Vec<String> {
+    SUPPORTED_CAPABILITIES.iter()
+        .filter(|capability| !disabled.iter().any(|disabled| disabled == *capability))
+        .map(|capability| capability.to_string())
+        .collect()
+}
+
+/// Write a handshake message to the stream, formated the same way as a Share (`content_length\n` followed by the bincode bytes)
+fn write_message<T: Serialize>(stream: &mut Connection, message: &T) -> Result<(), ShareError> {
+    let message = bincode::serialize(message)?;
+
+    stream.write_all(format!("{}\n", message.len()).as_bytes())?;
+    stream.write_all(&message)?;
+
+    Ok(())
+}
+
+/// Read a handshake message from the stream, messages larger than MAX_HELLO_SIZE are refused before anything is allocated
+fn read_message<T: for<'de> Deserialize<'de>>(stream: &mut Connection) -> Result<T, ShareError> {
+    let len = read_length(stream)?;
+
+    if len > MAX_HELLO_SIZE {
+        return Err(ShareError::Protocol(String::from("Handshake message is too large")));
+    }
+
+    let mut message = vec![0; len as usize];
+    stream.read_exact(&mut message)?;
+
+    Ok(bincode::deserialize(&message)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::test_util::connected;
+
+    /// Run the handshake on both ends, with the capabilities each side disabled
+    fn handshake(client_disabled: &[&str], server_disabled: &[&str]) -> (Handshake, Handshake) {
+        let (mut client, mut server) = connected();
+        let server_disabled: Vec<String> = server_disabled.iter().map(|capability| capability.to_string()).collect();
+        let client_disabled: Vec<String> = client_disabled.iter().map(|capability| capability.to_string()).collect();
+
+        let server = thread::spawn(move || Handshake::server(&mut server, &server_disabled).unwrap());
+        let client = Handshake::client(&mut client, &client_disabled).unwrap();
+
+        (client, server.join().unwrap())
+    }
+
+    /// Send a Hello to the server end and return its response along with the result on the server
+    fn send_hello(hello: Hello) -> (HelloResponse, Result<Handshake, ShareError>) {
+        let (mut client, mut server) = connected();
+        let server = thread::spawn(move || Handshake::server(&mut server, &[]));
+
+        write_message(&mut client, &hello).unwrap();
+        let response = read_message(&mut client).unwrap();
+
+        (response, server.join().unwrap())
+    }
+
+    #[test]
+    fn both_sides_agree_on_version_and_capabilities() {
+        let (client, server) = handshake(&[], &[]);
+        assert_eq!((client.version(), server.version()), (PROTOCOL_VERSION, PROTOCOL_VERSION));
+        assert!(client.supports(RESUME) && server.supports(RESUME));
+
+        // A capability either side disabled is used by neither
+        for (client, server) in [handshake(&[RESUME], &[]), handshake(&[], &[RESUME])] {
+            assert!(!client.supports(RESUME) && !server.supports(RESUME));
+        }
+    }
+
+    #[test]
+    fn newer_clients_use_the_version_of_the_server() {
+        let (response, result) = send_hello(Hello {
+            protocol: PROTOCOL_NAME.to_string(),
+            version: PROTOCOL_VERSION + 5,
+            min_version: MIN_PROTOCOL_VERSION,
+            capabilities: vec![RESUME.to_string(), String::from("unknown")],
+        });
+
+        assert!(response.accepted);
+        assert_eq!(response.version, PROTOCOL_VERSION);
+        assert_eq!(response.capabilities, [RESUME]);
+        assert_eq!(result.unwrap().version(), PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn unsupported_clients_are_rejected_with_a_reason() {
+        let old = Hello {
+            protocol: PROTOCOL_NAME.to_string(),
+            version: MIN_PROTOCOL_VERSION - 1,
+            min_version: 1,
+            capabilities: Vec::new(),
+        };
+        let other = Hello {
+            protocol: String::from("http"),
+            version: PROTOCOL_VERSION,
+            min_version: PROTOCOL_VERSION,
+            capabilities: Vec::new(),
+        };
+
+        for hello in [old, other] {
+            let (response, result) = send_hello(hello);
+
+            assert!(!response.accepted);
+            assert!(response.reason.is_some());
+            assert!(matches!(result, Err(ShareError::Protocol(_))));
+        }
+    }
+
+    #[test]
+    fn clients_report_the_reason_they_were_rejected() {
+        let (mut client, mut server) = connected();
+        let server = thread::spawn(move || {
+            let _: Hello = read_message(&mut server).unwrap();
+            reject(&mut server, "Server is full").unwrap();
+        });
+
+        let result = Handshake::client(&mut client, &[]);
+        server.join().unwrap();
+
+        assert!(matches!(result, Err(ShareError::Protocol(reason)) if reason.contains("Server is full")));
+    }
+}
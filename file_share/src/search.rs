@@ -0,0 +1,139 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{CatalogEntry, CatalogPage, EntryKind, ShareError, catalog::NameFilter, options::{CommandOptions, unknown_option}};
+
+/// Seconds in a day
+const DAY: u64 = 86_400;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+/// Contains what SEARCH looks for. Only files are matched, they have to match everything that is set. Matches are returned in
+/// pages like the catalog, sorted by path
+pub struct SearchQuery {
+    /// Only files with a name matching this are found, see NameFilter
+    pub name: Option<String>,
+    /// Only files with one of these extensions are found (without the dot, ignoring case), any extension if this is empty
+    pub extensions: Vec<String>,
+    /// Smallest size in bytes
+    pub min_size: Option<u64>,
+    /// Largest size in bytes
+    pub max_size: Option<u64>,
+    /// Only files changed at or after this time (in seconds since the Unix epoch) are found
+    pub modified_after: Option<u64>,
+    /// Only files changed before this time (in seconds since the Unix epoch) are found
+    pub modified_before: Option<u64>,
+    /// Amount of matches to skip
+    pub offset: u64,
+    /// Max amount of matches to return, the server never returns more than its catalog page size. None for a whole page
+    pub limit: Option<u64>,
+}
+
+impl SearchQuery {
+    /// Take the options of a SEARCH command out of its arguments, like `--name report* --ext pdf --min-size 1000
+    /// --after 2024-01-01`. Returns the arguments that are left
+    pub(crate) fn parse_options(args: Vec<String>) -> Result<(Vec<String>, SearchQuery), ShareError> {
+        let mut query = SearchQuery::default();
+        let mut options = CommandOptions::new(args);
+
+        while let Some(option) = options.next_option() {
+            match option.as_str() {
+                "--name" => query.name = Some(options.value(&option)?),
+                "--ext" => query.extensions.push(options.value(&option)?),
+                "--min-size" => query.min_size = Some(options.number(&option)?),
+                "--max-size" => query.max_size = Some(options.number(&option)?),
+                "--after" => query.modified_after = Some(parse_date(&options.value(&option)?)?),
+                "--before" => query.modified_before = Some(parse_date(&options.value(&option)?)?),
+                "--offset" => query.offset = options.number(&option)?,
+                "--limit" => query.limit = Some(options.number(&option)?),
+                _ => return Err(unknown_option(&option)),
+            }
+        }
+
+        Ok((options.rest(), query))
+    }
+    /// Returns the page of the matches the query asks for, it holds at most page_size matches
+    pub(crate) fn page(&self, mut matches: Vec<CatalogEntry>, page_size: u64) -> CatalogPage {
+        matches.sort_by(|a, b| a.name.cmp(&b.name));
+
+        CatalogPage::slice(matches, self.offset, self.limit, page_size)
+    }
+}
+
+/// Checks entries against a SearchQuery, the name filter is only built once
+pub(crate) struct SearchFilter<'a> {
+    query: &'a SearchQuery,
+    name: Option<NameFilter>,
+}
+
+impl SearchFilter<'_> {
+    pub(crate) fn new(query: &SearchQuery) -> Result<SearchFilter<'_>, ShareError> {
+        Ok(SearchFilter { query, name: query.name.as_deref().map(NameFilter::new).transpose()? })
+    }
+    /// Returns true if the entry is a file that matches everything the query asks for
+    pub(crate) fn matches(&self, entry: &CatalogEntry) -> bool {
+        let query = self.query;
+        let name = Path::new(&entry.name).file_name().unwrap_or_default().to_string_lossy();
+        let extension = Path::new(name.as_ref()).extension().map(|extension| extension.to_string_lossy().to_lowercase());
+
+        entry.kind == EntryKind::File &&
+            self.name.as_ref().is_none_or(|filter| filter.matches(&name)) &&
+            (query.extensions.is_empty() || query.extensions.iter().any(|wanted| {
+                extension.as_deref() == Some(wanted.trim_start_matches('.').to_lowercase().as_str())
+            })) &&
+            query.min_size.is_none_or(|min| entry.size >= min) &&
+            query.max_size.is_none_or(|max| entry.size <= max) &&
+            query.modified_after.is_none_or(|after| entry.modified.is_some_and(|modified| modified >= after)) &&
+            query.modified_before.is_none_or(|before| entry.modified.is_some_and(|modified| modified < before))
+    }
+}
+
+/// Parse a date like `2024-01-31` (midnight UTC) or a time in seconds since the Unix epoch
+pub fn parse_date(date: &str) -> Result<u64, ShareError> {
+    if let Ok(secs) = date.parse::<u64>() {
+        return Ok(secs);
+    }
+
+    let invalid = || ShareError::Parse(format!("{date} is not a date like 2024-01-31"));
+
+    let parts = date.split('-').map(|part| part.parse::<u64>()).collect::<Result<Vec<_>, _>>().map_err(|_| invalid())?;
+    let (year, month, day) = match parts[..] {
+        [year, month @ 1..=12, day @ 1..=31] if year >= 1970 => (year, month, day),
+        _ => return Err(invalid()),
+    };
+
+    // Count the days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    Ok(days * DAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_are_midnight_utc() {
+        assert_eq!(parse_date("1970-01-01").unwrap(), 0);
+        assert_eq!(parse_date("2000-03-01").unwrap(), 951_868_800);
+        assert_eq!(parse_date("2024-02-29").unwrap(), 1_709_164_800);
+        assert_eq!(parse_date("2024-12-31").unwrap(), 1_735_603_200);
+    }
+
+    #[test]
+    fn seconds_are_taken_as_they_are() {
+        assert_eq!(parse_date("1700000000").unwrap(), 1_700_000_000);
+    }
+
+    #[test]
+    fn invalid_dates_are_refused() {
+        for date in ["", "yesterday", "2024-13-01", "2024-00-10", "2024-01-32", "1969-12-31", "2024-01", "2024-01-01-01", "-5"] {
+            assert!(matches!(parse_date(date), Err(ShareError::Parse(_))), "{date:?} was parsed");
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use std::{
+    collections::HashSet, fs::{self, File}, io, path::{Path, PathBuf, Component}, sync::{Arc, Mutex}, time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{ShareError, ConflictPolicy, DEFAULT_CATALOG_PAGE_SIZE, PARTIAL_EXTENSION, is_partial, partial_path};
+
+#[derive(Debug, Clone)]
+/// Contains the directory the server stores its files in, all paths sent by clients are resolved inside of it
+pub struct Storage {
+    root: PathBuf,
+    /// Directory files that failed their checksum are moved to, they are removed if this is None
+    quarantine: Option<PathBuf>,
+    /// True if files are hashed when they are listed in the catalog
+    catalog_checksums: bool,
+    /// Most entries a single page of the catalog holds
+    catalog_page_size: u64,
+    /// What an upload does when a file is already at its path, if the client did not pick a policy
+    conflict_policy: ConflictPolicy,
+    /// Paths uploads are being received into right now, shared by every clone so two connections never write the same file
+    uploads: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+/// Keeps a path claimed for an upload until it is dropped, see Storage::claim_upload()
+pub(crate) struct UploadClaim {
+    uploads: Arc<Mutex<HashSet<PathBuf>>>,
+    path: PathBuf,
+}
+
+impl Drop for UploadClaim {
+    fn drop(&mut self) {
+        self.uploads.lock().unwrap().remove(&self.path);
+    }
+}
+
+impl Storage {
+    /// Create a new Storage, the root directory is created if it does not exist
+    pub fn build(root: &str) -> Result<Storage, ShareError> {
+        fs::create_dir_all(root)?;
+
+        Ok(Storage {
+            root: fs::canonicalize(root)?,
+            quarantine: None,
+            catalog_checksums: false,
+            catalog_page_size: DEFAULT_CATALOG_PAGE_SIZE,
+            conflict_policy: ConflictPolicy::default(),
+            uploads: Arc::default(),
+        })
+    }
+    /// Keep files that failed their checksum in the given directory instead of removing them, it is created if it does not exist.
+    /// This should be outside of the root so clients can not receive them
+    pub fn set_quarantine(&mut self, dir: &str) -> Result<(), ShareError> {
+        fs::create_dir_all(dir)?;
+        self.quarantine = Some(fs::canonicalize(dir)?);
+
+        Ok(())
+    }
+    /// Returns the directory files that failed their checksum are kept in
+    pub fn quarantine(&self) -> Option<&Path> {
+        self.quarantine.as_deref()
+    }
+    /// Hash every file listed in the catalog so clients can check them without receiving them first. This reads every listed file
+    /// each time the catalog is asked for
+    pub fn set_catalog_checksums(&mut self, catalog_checksums: bool) {
+        self.catalog_checksums = catalog_checksums;
+    }
+    /// Returns true if files are hashed when they are listed in the catalog
+    pub fn catalog_checksums(&self) -> bool {
+        self.catalog_checksums
+    }
+    /// Set the most entries a single page of the catalog holds, clients ask for the rest page by page
+    pub fn set_catalog_page_size(&mut self, catalog_page_size: u64) {
+        self.catalog_page_size = catalog_page_size.max(1);
+    }
+    /// Returns the most entries a single page of the catalog holds
+    pub fn catalog_page_size(&self) -> u64 {
+        self.catalog_page_size
+    }
+    /// Set what an upload does when a file is already at its path, used when the client did not pick a policy
+    pub fn set_conflict_policy(&mut self, conflict_policy: ConflictPolicy) {
+        self.conflict_policy = conflict_policy;
+    }
+    /// Returns what an upload does when a file is already at its path, if the client did not pick a policy
+    pub fn conflict_policy(&self) -> ConflictPolicy {
+        self.conflict_policy
+    }
+    /// Returns the root directory
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+    /// Resolve a path sent by a client into a path inside the storage root. Returns an error if the path is absolute, contains `..`,
+    /// leads outside of the root through a symlink or names a partial file. The path does not have to exist (so it can be used for
+    /// uploads)
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, ShareError> {
+        // Partial files belong to the uploads that write them, a client that could name them could swap out what gets stored
+        if Path::new(path).components().any(|component| is_partial(Path::new(component.as_os_str()))) {
+            return Err(ShareError::Path(format!("{path}: names ending in .{PARTIAL_EXTENSION} are reserved for partial uploads")));
+        }
+
+        self.resolve_inside(path)
+    }
+    /// Resolve a path into a path inside the storage root, like resolve() but partial files can be named
+    fn resolve_inside(&self, path: &str) -> Result<PathBuf, ShareError> {
+        // The root is resolved again in case it was moved or replaced with a symlink while running
+        let root = self.root.canonicalize()?;
+        let relative = Path::new(path);
+
+        for component in relative.components() {
+            match component {
+                Component::Normal(_) | Component::CurDir => (),
+                Component::ParentDir => {
+                    return Err(ShareError::Path(format!("{path}: `..` is not allowed")));
+                }
+                Component::RootDir | Component::Prefix(_) => {
+                    return Err(ShareError::Path(format!("{path}: absolute paths are not allowed")));
+                }
+            }
+        }
+
+        let full_path = root.join(relative);
+
+        // Find the deepest part of the path that exists (symlink_metadata() does not follow symlinks so dangling symlinks are
+        // found too), the root always exists so this ends there at the latest
+        let mut existing = full_path.as_path();
+        while fs::symlink_metadata(existing).is_err() {
+            existing = match existing.parent() {
+                Some(parent) => parent,
+                None => break,
+            };
+        }
+
+        // Follow any symlinks and make sure we are still inside the root
+        let resolved = existing.canonicalize().map_err(|_| {
+            ShareError::Path(format!("{path} could not be resolved"))
+        })?;
+        if !resolved.starts_with(&root) {
+            return Err(ShareError::Path(format!("{path} leads outside of the storage root")));
+        }
+
+        Ok(full_path)
+    }
+    /// Returns where a path sent by a client leads, relative to the root with every symlink followed. The ACL is checked against
+    /// this as well, so a symlink inside the root can not be used to reach a path the user may not use
+    pub fn resolve_target(&self, path: &str) -> Result<PathBuf, ShareError> {
+        let full_path = self.resolve(path)?;
+        let root = self.root.canonicalize()?;
+
+        // Only the part of the path that exists can be followed, the rest is added back on as it is
+        let mut existing = full_path.as_path();
+        let mut missing = Vec::new();
+        while fs::symlink_metadata(existing).is_err() {
+            missing.extend(existing.file_name());
+            existing = match existing.parent() {
+                Some(parent) => parent,
+                None => break,
+            };
+        }
+
+        let mut target = existing.canonicalize()?;
+        target.extend(missing.into_iter().rev());
+
+        // resolve() already made sure the path stays inside the root
+        Ok(target.strip_prefix(&root).map(Path::to_path_buf).unwrap_or_default())
+    }
+    /// Resolve a path sent by a client that names a file to write, like the target of an UPLOAD. On top of what resolve() checks,
+    /// the path has to end in a name (not the root, `.` or an empty path) so its partial file lands inside the root as well
+    pub fn resolve_file(&self, path: &str) -> Result<PathBuf, ShareError> {
+        if !matches!(Path::new(path).components().next_back(), Some(Component::Normal(_))) {
+            return Err(ShareError::Path(format!("{path:?} does not name a file")));
+        }
+
+        let full_path = self.resolve(path)?;
+        let partial = partial_path(Path::new(path));
+        self.resolve_inside(&partial.to_string_lossy())?;
+
+        Ok(full_path)
+    }
+    /// Claim a resolved path for an upload until the returned claim is dropped. Returns an error if another upload to the path is
+    /// running, both would write into the same partial file. name is the path as the client sent it
+    pub(crate) fn claim_upload(&self, path: &Path, name: &str) -> Result<UploadClaim, ShareError> {
+        let mut uploads = self.uploads.lock().unwrap();
+        if !uploads.insert(path.to_path_buf()) {
+            return Err(ShareError::Path(format!("{name} is already being uploaded")));
+        }
+
+        Ok(UploadClaim { uploads: Arc::clone(&self.uploads), path: path.to_path_buf() })
+    }
+    /// Move a file that failed its checksum into the quarantine directory, the time (and a number if that is taken) is added to its
+    /// name so earlier ones are not replaced. Returns where it was moved to, or None if there is no quarantine and the file was
+    /// removed
+    pub(crate) fn quarantine_file(&self, path: &Path, name: &str) -> Result<Option<PathBuf>, ShareError> {
+        let quarantine = match &self.quarantine {
+            Some(quarantine) => quarantine,
+            None => {
+                fs::remove_file(path)?;
+                return Ok(None);
+            }
+        };
+
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+        // The name is taken with an empty file first, so two files that fail at the same time never pick the same one
+        for number in 0.. {
+            let quarantined = match number {
+                0 => quarantine.join(format!("{name}.{time}")),
+                _ => quarantine.join(format!("{name}.{time}.{number}")),
+            };
+
+            match File::options().write(true).create_new(true).open(&quarantined) {
+                Ok(_) => {
+                    if let Err(error) = fs::rename(path, &quarantined) {
+                        // The empty file is of no use to anyone
+                        let _ = fs::remove_file(&quarantined);
+                        return Err(error.into());
+                    }
+
+                    return Ok(Some(quarantined));
+                }
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        unreachable!("ran out of numbers for {name}")
+    }
+}
+
+impl Default for Storage {
+    /// Storage rooted at the current directory
+    fn default() -> Storage {
+        Storage {
+            root: PathBuf::from("."),
+            quarantine: None,
+            catalog_checksums: false,
+            catalog_page_size: DEFAULT_CATALOG_PAGE_SIZE,
+            conflict_policy: ConflictPolicy::default(),
+            uploads: Arc::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+
+    #[test]
+    fn resolve_keeps_paths_inside_the_root() {
+        let dir = TestDir::new("resolve");
+        let storage = Storage::build(&dir.path("root")).unwrap();
+        let root = storage.root().to_path_buf();
+
+        assert_eq!(storage.resolve("docs/report.pdf").unwrap(), root.join("docs/report.pdf"));
+        assert_eq!(storage.resolve("./docs").unwrap(), root.join("docs"));
+        assert_eq!(storage.resolve(".").unwrap(), root);
+        assert_eq!(storage.resolve("").unwrap(), root);
+    }
+
+    #[test]
+    fn resolve_refuses_parent_and_absolute_paths() {
+        let dir = TestDir::new("resolve-escape");
+        let storage = Storage::build(&dir.path("root")).unwrap();
+
+        for path in ["..", "../secret", "docs/../../secret", "docs/..", "/etc/passwd", "/"] {
+            assert!(matches!(storage.resolve(path), Err(ShareError::Path(_))), "{path} was resolved");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolve_refuses_symlinks_out_of_the_root() {
+        use std::os::unix::fs::symlink;
+
+        let dir = TestDir::new("resolve-symlink");
+        let storage = Storage::build(&dir.path("root")).unwrap();
+        fs::create_dir(dir.path("outside")).unwrap();
+        symlink(dir.path("outside"), storage.root().join("link")).unwrap();
+        symlink(dir.path("outside/missing"), storage.root().join("dangling")).unwrap();
+        fs::create_dir(storage.root().join("inside")).unwrap();
+        symlink(storage.root().join("inside"), storage.root().join("inner_link")).unwrap();
+
+        assert!(storage.resolve("link").is_err());
+        assert!(storage.resolve("link/new.txt").is_err());
+        assert!(storage.resolve("dangling").is_err());
+        assert!(storage.resolve("inner_link/new.txt").is_ok());
+    }
+
+    #[test]
+    fn resolve_file_needs_a_name() {
+        let dir = TestDir::new("resolve-file");
+        let storage = Storage::build(&dir.path("root")).unwrap();
+
+        for path in [".", "", "./", "docs/.."] {
+            assert!(storage.resolve_file(path).is_err(), "{path:?} was resolved");
+        }
+        assert_eq!(storage.resolve_file("docs/report.pdf").unwrap(), storage.root().join("docs/report.pdf"));
+    }
+
+    #[test]
+    fn partial_files_can_not_be_named() {
+        let dir = TestDir::new("resolve-partial");
+        let storage = Storage::build(&dir.path("root")).unwrap();
+
+        for path in ["x.part", "docs/x.part", "x.part/file.txt", "./x.part/"] {
+            assert!(storage.resolve(path).is_err(), "{path} was resolved");
+            assert!(storage.resolve_file(path).is_err(), "{path} was resolved");
+        }
+        assert!(storage.resolve("x.partial").is_ok());
+        assert!(storage.resolve(".part").is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolve_target_follows_symlinks_inside_the_root() {
+        use std::os::unix::fs::symlink;
+
+        let dir = TestDir::new("resolve-target");
+        let storage = Storage::build(&dir.path("root")).unwrap();
+        fs::create_dir_all(storage.root().join("private/docs")).unwrap();
+        fs::create_dir(storage.root().join("public")).unwrap();
+        symlink(storage.root().join("private"), storage.root().join("public/link")).unwrap();
+
+        assert_eq!(storage.resolve_target("public/link/docs/new.txt").unwrap(), Path::new("private/docs/new.txt"));
+        assert_eq!(storage.resolve_target("public/other.txt").unwrap(), Path::new("public/other.txt"));
+        assert_eq!(storage.resolve_target(".").unwrap(), Path::new(""));
+    }
+
+    #[test]
+    fn quarantine_keeps_files_that_fail_at_the_same_time() {
+        let dir = TestDir::new("quarantine");
+        let mut storage = Storage::build(&dir.path("root")).unwrap();
+        storage.set_quarantine(&dir.path("quarantine")).unwrap();
+
+        let mut quarantined = Vec::new();
+        for body in ["first", "second", "third"] {
+            let path = storage.root().join("file.txt.part");
+            fs::write(&path, body).unwrap();
+            quarantined.push(storage.quarantine_file(&path, "file.txt").unwrap().unwrap());
+        }
+
+        let bodies: Vec<String> = quarantined.iter().map(|path| fs::read_to_string(path).unwrap()).collect();
+        assert_eq!(bodies, ["first", "second", "third"]);
+    }
+}
@@ -0,0 +1,42 @@
+use std::{env, fs, net::{TcpListener, TcpStream}, path::PathBuf, process};
+
+use crate::Connection;
+
+/// An empty directory for a single test, it is removed again when the test finishes
+pub(crate) struct TestDir(PathBuf);
+
+impl TestDir {
+    pub(crate) fn new(test: &str) -> TestDir {
+        let dir = env::temp_dir().join(format!("file_share-{}-{test}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        TestDir(dir)
+    }
+    /// Returns the path of something inside the directory
+    pub(crate) fn path(&self, name: &str) -> String {
+        self.0.join(name).to_string_lossy().to_string()
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Returns both ends of a TCP connection over localhost, the client end first
+pub(crate) fn tcp_pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+
+    (client, server)
+}
+
+/// Returns both ends of an unencrypted connection over localhost, the client end first
+pub(crate) fn connected() -> (Connection, Connection) {
+    let (client, server) = tcp_pair();
+
+    (Connection::Plain(client), Connection::Plain(server))
+}
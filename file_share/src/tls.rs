@@ -0,0 +1,263 @@
+use std::{fs::{self, File, OpenOptions}, io::{self, BufReader, Write}, net::TcpStream, path::Path, sync::Arc};
+
+use rustls::{
+    ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, ServerConfig, ServerConnection, SignatureScheme,
+    StreamOwned,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{self, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+};
+use sha2::{Digest, Sha256};
+
+use crate::{Checksum, Connection, ShareError};
+
+/// TLS settings of the server, these are loaded once and used for every connection
+pub struct TlsAcceptor {
+    config: Arc<ServerConfig>,
+}
+
+impl TlsAcceptor {
+    /// Load the certificate chain and private key (both PEM files) the server identifies itself with
+    pub fn build(cert_path: &str, key_path: &str) -> Result<TlsAcceptor, ShareError> {
+        let certs = read_certificates(cert_path)?;
+        let key = read_private_key(key_path)?;
+
+        let config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+
+        Ok(TlsAcceptor { config: Arc::new(config) })
+    }
+    /// Run the TLS handshake on a stream a client just opened
+    pub fn accept(&self, mut stream: TcpStream) -> Result<Connection, ShareError> {
+        let mut conn = ServerConnection::new(Arc::clone(&self.config))?;
+
+        while conn.is_handshaking() {
+            conn.complete_io(&mut stream).map_err(handshake_error)?;
+        }
+
+        Ok(Connection::TlsServer(Box::new(StreamOwned::new(conn, stream))))
+    }
+}
+
+/// TLS settings of the client, these decide which servers are trusted
+pub struct TlsConnector {
+    config: Arc<ClientConfig>,
+    /// Name the certificate of the server must be issued for, this can be an ip address
+    server_name: ServerName<'static>,
+}
+
+impl TlsConnector {
+    /// Trust servers with a certificate signed by the CA in the given PEM file, or only the server whose certificate has the given
+    /// SHA-256 fingerprint (see generate_certificate()). A pinned fingerprint is used over the CA if both are given
+    pub fn build(ca_path: Option<&str>, fingerprint: Option<&str>, server_name: &str) -> Result<TlsConnector, ShareError> {
+        let builder = ClientConfig::builder_with_provider(provider()).with_safe_default_protocol_versions()?;
+
+        let config = match (fingerprint, ca_path) {
+            (Some(fingerprint), _) => builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinnedCertificate::new(fingerprint)))
+                .with_no_client_auth(),
+            (None, Some(ca_path)) => {
+                let mut roots = RootCertStore::empty();
+                for cert in read_certificates(ca_path)? {
+                    roots.add(cert)?;
+                }
+
+                builder.with_root_certificates(roots).with_no_client_auth()
+            }
+            (None, None) => {
+                return Err(ShareError::Config(String::from("TLS needs a trusted CA or a pinned certificate fingerprint")));
+            }
+        };
+
+        let server_name = ServerName::try_from(server_name.to_string())
+            .map_err(|_| ShareError::Config(format!("{server_name} is not a valid server name")))?;
+
+        Ok(TlsConnector { config: Arc::new(config), server_name })
+    }
+    /// Run the TLS handshake on a stream that was just connected to the server, fails if the server is not trusted
+    pub fn connect(&self, mut stream: TcpStream) -> Result<Connection, ShareError> {
+        let mut conn = ClientConnection::new(Arc::clone(&self.config), self.server_name.clone())?;
+
+        while conn.is_handshaking() {
+            conn.complete_io(&mut stream).map_err(handshake_error)?;
+        }
+
+        Ok(Connection::TlsClient(Box::new(StreamOwned::new(conn, stream))))
+    }
+}
+
+/// Create a self-signed certificate for the given names (host names or ip addresses) and write it and its private key as PEM files.
+/// Existing files are never replaced. Returns the SHA-256 fingerprint of the certificate, clients can pin it to trust the server
+pub fn generate_certificate(names: Vec<String>, cert_path: &str, key_path: &str) -> Result<String, ShareError> {
+    for path in [cert_path, key_path] {
+        if Path::new(path).exists() {
+            return Err(ShareError::Config(format!("{path} already exists, remove it first to generate a new certificate")));
+        }
+    }
+
+    let certified = rcgen::generate_simple_self_signed(names).map_err(|error| ShareError::Tls(error.to_string()))?;
+
+    fs::write(cert_path, certified.cert.pem())?;
+    private_key_file(key_path)?.write_all(certified.key_pair.serialize_pem().as_bytes())?;
+
+    Ok(fingerprint(certified.cert.der()))
+}
+
+/// Create the file a private key is written to, on unix only the owner may read it
+fn private_key_file(path: &str) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(path)
+}
+
+/// Returns the SHA-256 fingerprint of a DER encoded certificate as hex
+pub fn fingerprint(cert: &[u8]) -> String {
+    Checksum::from_hasher(Sha256::new_with_prefix(cert)).to_string()
+}
+
+/// rustls reports a failed handshake (like an untrusted certificate) as an I/O error, those are turned back into a
+/// ShareError::Tls so they are not mistaken for a dropped connection
+fn handshake_error(error: io::Error) -> ShareError {
+    match error.get_ref().and_then(|inner| inner.downcast_ref::<rustls::Error>()) {
+        Some(tls_error) => ShareError::Tls(tls_error.to_string()),
+        None => error.into(),
+    }
+}
+
+/// Returns the crypto the library uses for TLS
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(crypto::ring::default_provider())
+}
+
+/// Read every certificate from a PEM file
+fn read_certificates(path: &str) -> Result<Vec<CertificateDer<'static>>, ShareError> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?)).collect::<Result<Vec<_>, _>>()?;
+
+    if certs.is_empty() {
+        return Err(ShareError::Config(format!("No certificates found in {path}")));
+    }
+
+    Ok(certs)
+}
+
+/// Read the first private key from a PEM file
+fn read_private_key(path: &str) -> Result<PrivateKeyDer<'static>, ShareError> {
+    rustls_pemfile::private_key(&mut BufReader::new(File::open(path)?))?
+        .ok_or_else(|| ShareError::Config(format!("No private key found in {path}")))
+}
+
+#[derive(Debug)]
+/// Trusts only the certificate with a given fingerprint, used for self-signed certificates that no CA vouches for
+struct PinnedCertificate {
+    /// Lowercase hex without separators
+    fingerprint: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl PinnedCertificate {
+    /// The fingerprint may be upper or lowercase and separated with `:` like most tools print it
+    fn new(fingerprint: &str) -> PinnedCertificate {
+        let fingerprint = fingerprint.chars()
+            .filter(|char| *char != ':')
+            .collect::<String>()
+            .to_lowercase();
+
+        PinnedCertificate { fingerprint, provider: provider() }
+    }
+}
+
+impl ServerCertVerifier for PinnedCertificate {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = fingerprint(end_entity);
+
+        if fingerprint == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(format!("Certificate fingerprint {fingerprint} does not match the pinned fingerprint")))
+        }
+    }
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{Handshake, PROTOCOL_VERSION, test_util::{TestDir, tcp_pair}};
+
+    #[test]
+    fn generated_certificate_connects_with_pinned_fingerprint() {
+        let dir = TestDir::new("tls");
+        let (cert_path, key_path) = (dir.path("cert.pem"), dir.path("key.pem"));
+        let fingerprint = generate_certificate(vec![String::from("127.0.0.1")], &cert_path, &key_path).unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&key_path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        let acceptor = TlsAcceptor::build(&cert_path, &key_path).unwrap();
+        let connector = TlsConnector::build(None, Some(&fingerprint), "127.0.0.1").unwrap();
+        let (client, server) = tcp_pair();
+
+        let server = thread::spawn(move || {
+            let mut stream = acceptor.accept(server).unwrap();
+            Handshake::server(&mut stream, &[]).unwrap()
+        });
+        let mut stream = connector.connect(client).unwrap();
+        let handshake = Handshake::client(&mut stream, &[]).unwrap();
+
+        assert_eq!(handshake.version(), PROTOCOL_VERSION);
+        assert_eq!(server.join().unwrap().version(), PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn other_fingerprints_are_not_trusted() {
+        let dir = TestDir::new("tls-pinned");
+        let (cert_path, key_path) = (dir.path("cert.pem"), dir.path("key.pem"));
+        generate_certificate(vec![String::from("127.0.0.1")], &cert_path, &key_path).unwrap();
+
+        let acceptor = TlsAcceptor::build(&cert_path, &key_path).unwrap();
+        let connector = TlsConnector::build(None, Some(&"0".repeat(64)), "127.0.0.1").unwrap();
+        let (client, server) = tcp_pair();
+
+        let server = thread::spawn(move || acceptor.accept(server).is_err());
+
+        assert!(matches!(connector.connect(client), Err(ShareError::Tls(_))));
+        assert!(server.join().unwrap());
+    }
+}
@@ -0,0 +1,176 @@
+use std::{fs, io::Write};
+
+use argon2::{
+    Argon2,
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{Connection, Location, Share, ShareCommandBuilder, CommandType, ShareError};
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+/// Contains the user name and password a client logs in with
+pub struct Credentials {
+    pub user: String,
+    pub password: String,
+}
+
+impl std::fmt::Debug for Credentials {
+    /// The password is left out so it never ends up in a log
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials").field("user", &self.user).finish_non_exhaustive()
+    }
+}
+
+#[derive(Deserialize)]
+/// Layout of the users file
+struct UsersFile {
+    #[serde(default)]
+    user: Vec<User>,
+}
+
+#[derive(Deserialize, Debug)]
+/// Contains a single user that can log in to the server
+pub struct User {
+    name: String,
+    /// Argon2 hash in the PHC string format, see hash_password()
+    password: String,
+}
+
+impl User {
+    /// Returns the name the user logs in with
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[derive(Debug)]
+/// Contains every user that can log in to the server, these are read from a TOML file with a `[[user]]` table for each user
+pub struct Users {
+    users: Vec<User>,
+    /// Checked when the user name is unknown, so a wrong name takes as long as a wrong password
+    unknown_user_hash: String,
+}
+
+impl Users {
+    /// Read the users file at the given path, every password hash is checked so a broken entry is found right away
+    pub fn build(path: &str) -> Result<Users, ShareError> {
+        let file = fs::read_to_string(path)
+            .map_err(|error| ShareError::Config(format!("Could not read users file {path}: {error}")))?;
+        let file: UsersFile = toml::from_str(&file)?;
+
+        for user in &file.user {
+            PasswordHash::new(&user.password)
+                .map_err(|error| ShareError::Config(format!("Password hash of {} is not valid: {error}", user.name)))?;
+        }
+
+        Ok(Users { users: file.user, unknown_user_hash: hash_password("")? })
+    }
+    /// Returns the user the credentials belong to, or ShareError::Auth if the name or password is wrong. Which of the two was
+    /// wrong is not given away
+    pub fn verify(&self, credentials: &Credentials) -> Result<&User, ShareError> {
+        let user = self.users.iter().find(|user| user.name == credentials.user);
+        let hash = user.map_or(self.unknown_user_hash.as_str(), |user| user.password.as_str());
+
+        let verified = PasswordHash::new(hash).is_ok_and(|hash| {
+            Argon2::default().verify_password(credentials.password.as_bytes(), &hash).is_ok()
+        });
+
+        match user {
+            Some(user) if verified => Ok(user),
+            _ => Err(ShareError::Auth(String::from("Wrong user name or password"))),
+        }
+    }
+    /// Returns the amount of users
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+    /// Returns true if nobody can log in
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+}
+
+/// Log in on the server, this is sent right after the handshake. Returns the error the server reported as a ShareError::Server
+/// if the credentials were refused
+pub fn login(stream: &mut Connection, credentials: Credentials) -> Result<(), ShareError> {
+    let mut share = Share::new(ShareCommandBuilder::new().command_type(CommandType::Login).build(), Location::Client);
+    share.set_credentials(credentials);
+
+    share.write_to_stream(stream, Location::Client)?;
+    stream.flush()?;
+
+    Share::read_from_stream(stream, Location::Client)?.server_result()
+}
+
+/// Hash a password with Argon2 and a random salt, the result can be put in the users file
+pub fn hash_password(password: &str) -> Result<String, ShareError> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|error| ShareError::Auth(format!("Could not hash password: {error}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::test_util::{TestDir, connected};
+
+    fn credentials(user: &str, password: &str) -> Credentials {
+        Credentials { user: user.to_string(), password: password.to_string() }
+    }
+
+    fn users(dir: &TestDir) -> Users {
+        let path = dir.path("users.toml");
+        fs::write(&path, format!("[[user]]\nname = 'alice'\npassword = '{}'\n", hash_password("secret").unwrap())).unwrap();
+
+        Users::build(&path).unwrap()
+    }
+
+    #[test]
+    fn verify_needs_a_known_user_and_their_password() {
+        let dir = TestDir::new("users");
+        let users = users(&dir);
+
+        assert_eq!(users.verify(&credentials("alice", "secret")).unwrap().name(), "alice");
+        assert!(matches!(users.verify(&credentials("alice", "wrong")), Err(ShareError::Auth(_))));
+        assert!(matches!(users.verify(&credentials("bob", "secret")), Err(ShareError::Auth(_))));
+    }
+
+    #[test]
+    fn broken_password_hashes_are_refused() {
+        let dir = TestDir::new("users-broken");
+        let path = dir.path("users.toml");
+        fs::write(&path, "[[user]]\nname = 'alice'\npassword = 'secret'\n").unwrap();
+
+        assert!(matches!(Users::build(&path), Err(ShareError::Config(_))));
+    }
+
+    #[test]
+    fn login_reports_refused_credentials() {
+        let dir = TestDir::new("users-login");
+        let users = users(&dir);
+        let (mut client, mut server) = connected();
+
+        // Answers two logins the way the server does
+        let server = thread::spawn(move || {
+            for _ in 0..2 {
+                let mut share = Share::read_from_stream(&mut server, Location::Server).unwrap();
+                let credentials = share.take_credentials().unwrap();
+                if let Err(error) = users.verify(&credentials) {
+                    share.set_error_response(error);
+                }
+                share.write_to_stream(&mut server, Location::Server).unwrap();
+            }
+        });
+
+        assert_eq!(login(&mut client, credentials("alice", "secret")), Ok(()));
+        let refused = login(&mut client, credentials("alice", "wrong"));
+        assert!(matches!(refused, Err(ShareError::Server(error)) if matches!(*error, ShareError::Auth(_))));
+        server.join().unwrap();
+    }
+}
@@ -1,14 +1,73 @@
 #![feature(buf_read_has_data_left)]
-use std::{net::{TcpListener, TcpStream}, io::Write, process};
+use std::{
+    net::{SocketAddr, TcpListener, TcpStream}, io::{self, Write}, process, sync::{Arc, RwLock, mpsc}, env, thread, time::{Duration, SystemTime}, fs,
+};
 
-use file_share::{Share, Location, Config};
+use file_share::{
+    Share, Location, Config, ShareLimits, ShareCommandBuilder, ShareError, Storage, Handshake, RESUME, Connection, TlsAcceptor,
+    generate_certificate, Users, CommandType, Credentials, hash_password, Acl, partial_path, IoErrorKind,
+};
 
 mod threadpool;
+mod sessions;
 
 use threadpool::ThreadPool;
+use sessions::Sessions;
+
+/// Configuration file of the server
+const CONFIG_PATH: &str = "Config.toml";
+/// How often the configuration file is checked for changes to the ACL
+const ACL_RELOAD_DELAY: Duration = Duration::from_secs(2);
+/// How long the server waits before answering a failed login, this slows down guessing passwords
+const LOGIN_FAILURE_DELAY: Duration = Duration::from_secs(1);
+/// Failed logins allowed on a single connection before it is closed
+const MAX_LOGIN_ATTEMPTS: u32 = 3;
+/// How often the listeners check for new connections, and for whether the server is shutting down
+const ACCEPT_POLL_DELAY: Duration = Duration::from_millis(100);
+
+/// Contains the parts of the servers configuration every connection needs
+struct Settings {
+    limits: ShareLimits,
+    storage: Storage,
+    disabled_capabilities: Vec<String>,
+    /// Connections are encrypted if this is set
+    tls: Option<TlsAcceptor>,
+    /// Users that can log in, anyone can connect without logging in if this is None
+    users: Option<Users>,
+    /// Which users may do what with which paths, this is replaced whenever the configuration file changes
+    acl: RwLock<Arc<Acl>>,
+    /// Connections that are being handled, the server waits for them when it shuts down
+    sessions: Sessions,
+    /// How long a single read or write may take, None if there is no limit
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    /// How long the server waits for the next command, None if there is no limit
+    idle_timeout: Option<Duration>,
+}
 
 fn main() {
-    let config = Config::build("Config.toml").unwrap_or_else(|error| {
+    // `server hash-password` reads a password from stdin and prints its hash for the users file
+    if env::args().nth(1).as_deref() == Some("hash-password") {
+        let mut password = String::new();
+        if let Err(error) = io::stdin().read_line(&mut password) {
+            eprintln!("Failed to read password: {error}");
+            process::exit(1);
+        }
+
+        match hash_password(password.trim_end_matches(['\r', '\n'])) {
+            Ok(hash) => {
+                println!("Add this to the users file, with the name the user logs in with:");
+                println!("[[user]]\nname = ''\npassword = '{hash}'");
+                process::exit(0);
+            }
+            Err(error) => {
+                eprintln!("{error}");
+                process::exit(1);
+            }
+        }
+    }
+
+    let config = Config::build(CONFIG_PATH).unwrap_or_else(|error| {
         eprintln!("Config build error: {error}");
         process::exit(1);
     }).server().unwrap_or_else(|error| {
@@ -16,6 +75,78 @@ fn main() {
         process::exit(1);
     });
 
+    // `server generate-cert [name...]` creates a self-signed certificate for first time setup, the names default to localhost
+    // and the configured ip's
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("generate-cert") {
+        let (cert, key) = match (config.tls_cert(), config.tls_key()) {
+            (Some(cert), Some(key)) => (cert, key),
+            _ => {
+                eprintln!("Set tls_cert and tls_key in Config.toml to where the certificate should be written");
+                process::exit(1);
+            }
+        };
+
+        let mut names = args[1..].to_vec();
+        if names.is_empty() {
+            names.push(String::from("localhost"));
+            for ip in config.ips() {
+                if let Some((host, _)) = ip.rsplit_once(':') {
+                    let host = host.trim_start_matches('[').trim_end_matches(']').to_string();
+                    if !names.contains(&host) {
+                        names.push(host);
+                    }
+                }
+            }
+        }
+
+        match generate_certificate(names.clone(), cert, key) {
+            Ok(fingerprint) => {
+                println!("Wrote a certificate for {names:?} to {cert} and its key to {key}");
+                println!("Trust it on clients by adding this to their Config.toml:\ntls_fingerprint = '{fingerprint}'");
+                process::exit(0);
+            }
+            Err(error) => {
+                eprintln!("Failed to generate certificate: {error}");
+                process::exit(1);
+            }
+        }
+    }
+
+    // Load the certificate before anything is bound so a bad TLS setup never falls back to plain connections
+    let tls = config.tls().and_then(|tls| {
+        tls.map(|(cert, key)| TlsAcceptor::build(cert, key)).transpose()
+    }).unwrap_or_else(|error| {
+        eprintln!("Failed to set up TLS: {error}");
+        process::exit(1);
+    });
+    match tls {
+        Some(_) => println!("Connections are encrypted with TLS"),
+        None => println!("TLS is not set up, connections are NOT encrypted"),
+    }
+
+    let users = config.users_file().map(Users::build).transpose().unwrap_or_else(|error| {
+        eprintln!("Failed to load users: {error}");
+        process::exit(1);
+    });
+    match &users {
+        Some(users) => println!("{} users can log in", users.len()),
+        None => println!("No users file is set, anyone can connect without logging in"),
+    }
+
+    // LOGIN sends the password as it is, so it must never go over a connection anyone on the network can read
+    if users.is_some() && tls.is_none() {
+        eprintln!("A users file is set but TLS is not, passwords would be sent unencrypted. Set tls_cert and tls_key as well");
+        process::exit(1);
+    }
+
+    // Without a users file nobody can prove who they are, so rules for named users could be claimed by anyone
+    let acl = config.acl();
+    if users.is_none() && acl.has_named_users() {
+        eprintln!("The ACL has rules for named users but no users file is set");
+        process::exit(1);
+    }
+
     // Create a new thread pool
     let pool = match ThreadPool::build(config.thread_count()) {
         Ok(p) => p,
@@ -40,38 +171,132 @@ fn main() {
         }
     };
 
-    // Create a TcpListener and attempt to bind to the given ip
-    let listener = TcpListener::bind(config.ip()).unwrap_or_else(|error| {
-        eprintln!("Failed in binding to address: {error}. Trying to connect to backups");
+    // All files the server shares are kept inside of the storage root
+    let mut storage = Storage::build(config.storage_root()).unwrap_or_else(|error| {
+        eprintln!("Failed to open storage root {}: {error}", config.storage_root());
+        process::exit(1);
+    });
+    println!("Storing files in {}", storage.root().display());
 
-        // Loop through the vector of ip backups and try to connect to one until a success
-        for (i, ip) in config.ip_backups().iter().enumerate() {
-            match TcpListener::bind(ip) {
-                Ok(listener) => {
-                    println!("Backup ip: {i} successfully bound");
-                    return listener
-                }
-                Err(error) => {
-                    println!("Backup ip: {i} failed to bind: {error}");
-                    continue;
-                }
+    // Uploads that fail their checksum are kept here so they can be looked at
+    if let Some(quarantine_dir) = config.quarantine_dir() {
+        storage.set_quarantine(quarantine_dir).unwrap_or_else(|error| {
+            eprintln!("Failed to open quarantine directory {quarantine_dir}: {error}");
+            process::exit(1);
+        });
+        println!("Quarantining bad uploads in {}", storage.quarantine().unwrap().display());
+    }
+    storage.set_catalog_checksums(config.catalog_checksums());
+    storage.set_catalog_page_size(config.catalog_page_size());
+    storage.set_conflict_policy(config.upload_conflict());
+
+    // Shared by every connection
+    let settings = Arc::new(Settings {
+        limits: config.limits(),
+        storage,
+        disabled_capabilities: config.disabled_capabilities().to_vec(),
+        tls,
+        users,
+        acl: RwLock::new(Arc::new(acl)),
+        sessions: Sessions::default(),
+        read_timeout: config.read_timeout(),
+        write_timeout: config.write_timeout(),
+        idle_timeout: config.idle_timeout(),
+    });
+    println!("Loaded ACL with {} rules", settings.acl.read().unwrap().len());
+
+    // Pick up ACL changes without a restart
+    let watched_settings = Arc::clone(&settings);
+    thread::spawn(move || watch_acl(&watched_settings));
+
+    // Bind every configured ip, the ones that fail are skipped so one missing interface does not take the whole server down
+    let mut listeners = Vec::new();
+    for ip in config.ips() {
+        // The listeners are polled so they can notice the server shutting down
+        let listener = TcpListener::bind(ip).and_then(|listener| {
+            listener.set_nonblocking(true)?;
+            Ok(listener)
+        });
+
+        match listener {
+            Ok(listener) => {
+                println!("Listening on {ip}");
+                listeners.push(listener);
             }
+            Err(error) => eprintln!("Failed to bind {ip}: {error}"),
         }
+    }
+    if listeners.is_empty() {
+        eprintln!("None of the configured ip's could be bound!");
+        process::exit(1);
+    }
 
-        eprintln!("All backup ip's failed to bind!");
-
+    // Shut down on Ctrl-C or SIGTERM, a second one stops right away without waiting for anything
+    let (signal_sender, signal_receiver) = mpsc::channel();
+    let mut signalled = false;
+    ctrlc::set_handler(move || {
+        if signalled {
+            eprintln!("Stopping right away");
+            process::exit(1);
+        }
+        signalled = true;
+        let _ = signal_sender.send(());
+    }).unwrap_or_else(|error| {
+        eprintln!("Failed to set up the shutdown handler: {error}");
         process::exit(1);
     });
+    let shutdown_timeout = Duration::from_secs(config.shutdown_timeout());
+
+    // Every listener accepts on its own thread, the connections all go to the same pool
+    thread::scope(|scope| {
+        for listener in &listeners {
+            scope.spawn(|| accept_connections(listener, &pool, &settings));
+        }
+
+        // The accept loops end on their own once the sessions are stopping
+        let _ = signal_receiver.recv();
+        println!(
+            "Shutting down, waiting up to {} seconds for {} connections to finish",
+            shutdown_timeout.as_secs(), settings.sessions.len(),
+        );
+        settings.sessions.stop();
+    });
+
+    // Commands that are still running once the deadline passed are cut off, their partial uploads are removed
+    if !settings.sessions.wait(shutdown_timeout) {
+        eprintln!("{} connections did not finish in time, closing them", settings.sessions.len());
+        settings.sessions.close_all();
+    }
+
+    // Every worker finishes the job it is running before it is joined, the closed connections make sure that happens quickly
+    drop(pool);
+    println!("Server stopped");
+}
+
+/// Accept connections on the listener until the server shuts down, each one is handled by the pool
+fn accept_connections(listener: &TcpListener, pool: &ThreadPool, settings: &Arc<Settings>) {
+    let local_addr = match listener.local_addr() {
+        Ok(local_addr) => local_addr.to_string(),
+        Err(error) => {
+            eprintln!("Failed to read the address the server listens on: {error}");
+            String::from("an unknown address")
+        }
+    };
 
     // Loop through each connection
-    for stream in listener.incoming() {
+    while !settings.sessions.stopping() {
         // Get the value inside stream
-        let stream = match stream {
+        let (stream, peer_addr) = match listener.accept() {
             // Connection success
-            Ok(stream) => {
-                println!("Client {:?} connected", stream.peer_addr());
-                stream
+            Ok((stream, peer_addr)) => {
+                println!("Client {peer_addr} connected on {local_addr}");
+                (stream, peer_addr)
             },
+            // Nothing to accept yet
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_POLL_DELAY);
+                continue;
+            }
             // Conection failed
             Err(error) => {
                 // Print log and continue
@@ -80,9 +305,17 @@ fn main() {
             }
         };
 
+        // Only the listener is polled, the connection itself blocks like normal
+        if let Err(error) = stream.set_nonblocking(false) {
+            eprintln!("Connection to client failed: {error}");
+            continue;
+        }
+
+        let settings = Arc::clone(settings);
+
         // Execute the handle_client() function for each connection
-        pool.execute(|| {
-            handle_client(stream)
+        pool.execute(move || {
+            handle_client(stream, peer_addr, &settings)
         });
     }
 }
@@ -90,12 +323,86 @@ fn main() {
 /// Only the official client will work for the most part so the server wont have
 /// to handle additional things like making sure your command was correct (this
 /// is checked on the official client)
-fn handle_client(mut stream: TcpStream) {
+fn handle_client(stream: TcpStream, peer_addr: SocketAddr, settings: &Settings) {
+    // Connections that were waiting in the pool while the server started shutting down are closed right away
+    let session = match settings.sessions.register(&stream) {
+        Some(session) => session,
+        None => return,
+    };
+
+    // A client that stops sending or taking data is dropped once a timeout passes, instead of holding a worker forever
+    let timeouts = stream.set_read_timeout(settings.read_timeout).and_then(|_| stream.set_write_timeout(settings.write_timeout));
+    if let Err(error) = timeouts {
+        eprintln!("Failed to set timeouts: {error}: Client ip {peer_addr}");
+        return;
+    }
+
+    // The TLS handshake runs here instead of the accept loop so a slow client does not hold up everyone else
+    let mut stream = match &settings.tls {
+        Some(tls) => match tls.accept(stream) {
+            Ok(connection) => connection,
+            Err(error) => {
+                eprintln!("{error}: Client ip {peer_addr}");
+                return;
+            }
+        },
+        None => Connection::Plain(stream),
+    };
+
+    // Agree on a protocol version and capabilities before anything else is sent, clients that are too old or too new are told why
+    // they were rejected
+    let handshake = match Handshake::server(&mut stream, &settings.disabled_capabilities) {
+        Ok(handshake) => handshake,
+        Err(error) => {
+            eprintln!("{error}: Client ip {peer_addr}");
+            return;
+        }
+    };
+    println!(
+        "Client {peer_addr} using protocol version {} with capabilities {:?}",
+        handshake.version(), handshake.capabilities(),
+    );
+
+    // Set once the client logged in, commands run as this user from then on
+    let mut user: Option<String> = None;
+    let mut failed_logins = 0;
+
     loop {
+        // Clients may take as long as the idle timeout to send their next command, PING keeps quiet ones connected
+        if let Err(error) = stream.tcp().set_read_timeout(settings.idle_timeout) {
+            eprintln!("Failed to set timeouts: {error}: Client ip {peer_addr}");
+            return;
+        }
+
         // Read data that was sent from client
-        let mut share = match Share::read_from_stream(&mut stream, Location::Server) {
+        let share = Share::read_from_stream_limited(&mut stream, Location::Server, settings.limits);
+
+        // The file body and anything else the command reads has to keep arriving
+        if let Err(error) = stream.tcp().set_read_timeout(settings.read_timeout) {
+            eprintln!("Failed to set timeouts: {error}: Client ip {peer_addr}");
+            return;
+        }
+
+        let mut share = match share {
             // Successful read
             Ok(share) => share,
+            Err(ShareError::Io { kind: IoErrorKind::TimedOut, .. }) => {
+                println!("Client {peer_addr} was idle for too long, closing connection");
+                return;
+            }
+            // Share was too large to be read, let the client know which limit was hit. The connection is closed after since the
+            // rest of the share can not be skipped safely
+            Err(error @ ShareError::Limit(_)) => {
+                eprintln!("{error}: Client ip {peer_addr}");
+
+                let mut share = Share::new(ShareCommandBuilder::new().build(), Location::Server);
+                share.set_error_response(error);
+
+                if let Err(error) = share.write_to_stream(&mut stream, Location::Server) {
+                    eprintln!("Failed to write to stream: {error}");
+                }
+                return;
+            }
             // Invalid read
             Err(error) => {
                 eprintln!("{error}");
@@ -106,15 +413,58 @@ fn handle_client(mut stream: TcpStream) {
             }
         };
 
-        // Execute the recieved command
-        if let Err(e) = share.execute() {
+        // Nothing new is started once the server is shutting down
+        if !session.start_command() {
+            return;
+        }
+
+        // Execute the recieved command inside of the storage root, as the user that logged in
+        share.set_storage(settings.storage.clone());
+        share.set_access(Arc::clone(&settings.acl.read().unwrap()), user.clone());
+        // Taken out of every share so they are never sent back
+        let credentials = share.take_credentials();
+
+        let result = if share.command().command_type() == Some(&CommandType::Login) {
+            match log_in(credentials, settings) {
+                Ok(name) => {
+                    println!("Client {peer_addr} logged in as {name}");
+                    user = Some(name);
+                    Ok(())
+                }
+                Err(error) => {
+                    eprintln!("{error}: Client ip {peer_addr}");
+                    failed_logins += 1;
+                    thread::sleep(LOGIN_FAILURE_DELAY);
+                    Err(error)
+                }
+            }
+        } else if settings.users.is_some() && user.is_none() {
+            Err(ShareError::Auth(String::from("Log in before sending commands")))
+        } else if share.command().uses_resume() && !handshake.supports(RESUME) {
+            Err(ShareError::Protocol(String::from("Resuming transfers was not agreed on during the handshake")))
+        } else {
+            share.execute(&mut stream)
+        };
+        // The shutdown deadline cut this upload off, it is not kept for resuming
+        if result.is_err() && session.forced() && share.command().command_type() == Some(&CommandType::Upload) {
+            remove_partial_upload(share.command().arg().unwrap_or_default(), settings);
+        }
+        if let Err(e) = result {
             // If there was an error set the servers error response
             share.set_error_response(e);
         };
 
+        // Throw away a file body that was not read because of an error so the next share can be read
+        if let Err(error) = share.discard_body(&mut stream) {
+            eprintln!("Failed to discard file body: {error}");
+            return;
+        }
+
         // Write share to stream since we executed the command and all the data needed is inside
         match share.write_to_stream(&mut stream, Location::Server) {
             Ok(_) => (),
+            // The client stopped the file body it asked for, the stream is still usable
+            Err(ShareError::Cancelled) => println!("Client {peer_addr} cancelled a transfer"),
             Err(error) => {
                 eprintln!("Failed to write to stream: {error}");
                 return;
@@ -122,7 +472,71 @@ fn handle_client(mut stream: TcpStream) {
         }
 
         stream.flush().unwrap_or_else(|error| {
-            eprintln!("Failed to flush stream: {error}: Client ip {}", stream.peer_addr().unwrap());
+            eprintln!("Failed to flush stream: {error}: Client ip {peer_addr}");
         });
+
+        if failed_logins >= MAX_LOGIN_ATTEMPTS {
+            eprintln!("Too many failed logins, closing connection: Client ip {peer_addr}");
+            return;
+        }
+
+        if !session.finish_command() {
+            println!("Server is shutting down, closing connection: Client ip {peer_addr}");
+            return;
+        }
+    }
+}
+
+/// Remove what arrived of an upload to the given path
+fn remove_partial_upload(path: &str, settings: &Settings) {
+    let part_path = match settings.storage.resolve(path) {
+        Ok(path) => partial_path(&path),
+        Err(_) => return,
+    };
+
+    match fs::remove_file(&part_path) {
+        Ok(()) => println!("Removed partial upload {}", part_path.display()),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+        Err(error) => eprintln!("Failed to remove partial upload {}: {error}", part_path.display()),
+    }
+}
+
+/// Reload the ACL whenever the configuration file changes. A configuration that can not be read keeps the old ACL in place
+fn watch_acl(settings: &Settings) {
+    let modified = || fs::metadata(CONFIG_PATH).and_then(|metadata| metadata.modified()).ok();
+    let mut last_modified: Option<SystemTime> = modified();
+
+    loop {
+        thread::sleep(ACL_RELOAD_DELAY);
+
+        let current = modified();
+        if current == last_modified {
+            continue;
+        }
+        last_modified = current;
+
+        match Config::build(CONFIG_PATH).and_then(Config::server) {
+            Ok(config) => {
+                let acl = config.acl();
+                if settings.users.is_none() && acl.has_named_users() {
+                    eprintln!("Failed to reload ACL, keeping the old one: it has rules for named users but no users file is set");
+                    continue;
+                }
+                println!("Reloaded ACL with {} rules", acl.len());
+                *settings.acl.write().unwrap() = Arc::new(acl);
+            }
+            Err(error) => eprintln!("Failed to reload ACL, keeping the old one: {error}"),
+        }
+    }
+}
+
+/// Check the credentials sent with LOGIN, returns the name of the user that logged in
+fn log_in(credentials: Option<Credentials>, settings: &Settings) -> Result<String, ShareError> {
+    let credentials = credentials.ok_or_else(|| ShareError::Auth(String::from("No credentials were sent")))?;
+
+    match &settings.users {
+        Some(users) => users.verify(&credentials).map(|user| user.name().to_string()),
+        // There is nothing to check the name against, taking it as given would let anyone pick who they are
+        None => Err(ShareError::Auth(String::from("Logging in is not set up on this server"))),
     }
 }
\ No newline at end of file
@@ -0,0 +1,132 @@
+use std::{
+    collections::HashMap,
+    net::{Shutdown, TcpStream},
+    sync::{Condvar, Mutex},
+    time::Duration,
+};
+
+/// Keeps track of the connections that are being handled, so the server can wait for them to finish when it shuts down
+#[derive(Default)]
+pub struct Sessions {
+    state: Mutex<State>,
+    /// Notified whenever a session ends
+    ended: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    sessions: HashMap<u64, Session>,
+    next_id: u64,
+    /// Set once the server started shutting down, sessions close after the command they are running
+    stopping: bool,
+    /// Set once the deadline passed and every connection was closed, commands that were cut off clean up after themselves
+    forced: bool,
+}
+
+struct Session {
+    /// A handle to the socket of the connection, used to close it from another thread
+    stream: TcpStream,
+    /// True while a command is running, false while the session waits for the next one
+    busy: bool,
+}
+
+impl Sessions {
+    /// Start keeping track of a connection, the returned guard stops when it is dropped. Returns None if the server is shutting
+    /// down
+    pub fn register(&self, stream: &TcpStream) -> Option<SessionGuard<'_>> {
+        let stream = match stream.try_clone() {
+            Ok(stream) => stream,
+            Err(error) => {
+                eprintln!("Failed to keep track of connection: {error}");
+                return None;
+            }
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if state.stopping {
+            return None;
+        }
+
+        let id = state.next_id;
+        state.next_id += 1;
+        state.sessions.insert(id, Session { stream, busy: false });
+
+        Some(SessionGuard { sessions: self, id })
+    }
+    /// Returns true once the server started shutting down
+    pub fn stopping(&self) -> bool {
+        self.state.lock().unwrap().stopping
+    }
+    /// Stop starting new commands. Connections that are waiting for their next command are closed right away, the others are
+    /// closed once their command finished
+    pub fn stop(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.stopping = true;
+
+        for session in state.sessions.values().filter(|session| !session.busy) {
+            // The connection may already be closed by the client
+            let _ = session.stream.shutdown(Shutdown::Both);
+        }
+    }
+    /// Wait for every session to end, returns false if some were still running once the timeout passed
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let state = self.state.lock().unwrap();
+        let (state, _) = self.ended.wait_timeout_while(state, timeout, |state| !state.sessions.is_empty()).unwrap();
+
+        state.sessions.is_empty()
+    }
+    /// Close every connection that is left, the commands they were running fail on their next read or write
+    pub fn close_all(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.forced = true;
+
+        for session in state.sessions.values() {
+            let _ = session.stream.shutdown(Shutdown::Both);
+        }
+    }
+    /// Returns the amount of sessions that are running
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().sessions.len()
+    }
+}
+
+/// Keeps a session registered in Sessions while it is alive
+pub struct SessionGuard<'a> {
+    sessions: &'a Sessions,
+    id: u64,
+}
+
+impl SessionGuard<'_> {
+    /// Mark the session as running a command. Returns false if the server is shutting down, the command should not be run
+    pub fn start_command(&self) -> bool {
+        self.set_busy(true)
+    }
+    /// Mark the session as waiting for its next command. Returns false if the server is shutting down, the connection should
+    /// be closed
+    pub fn finish_command(&self) -> bool {
+        self.set_busy(false)
+    }
+    /// Returns true if the connection was closed because the shutdown deadline passed
+    pub fn forced(&self) -> bool {
+        self.sessions.state.lock().unwrap().forced
+    }
+    fn set_busy(&self, busy: bool) -> bool {
+        let mut state = self.sessions.state.lock().unwrap();
+        if state.stopping {
+            return false;
+        }
+
+        if let Some(session) = state.sessions.get_mut(&self.id) {
+            session.busy = busy;
+        }
+
+        true
+    }
+}
+
+impl Drop for SessionGuard<'_> {
+    fn drop(&mut self) {
+        self.sessions.state.lock().unwrap().sessions.remove(&self.id);
+        self.sessions.ended.notify_all();
+    }
+}